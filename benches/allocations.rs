@@ -0,0 +1,95 @@
+#![feature(test)]
+extern crate trie;
+#[macro_use]
+extern crate criterion;
+extern crate jemallocator;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// jemalloc, counting every allocation made through it
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+    jemallocator::Jemalloc.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    jemallocator::Jemalloc.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+    jemallocator::Jemalloc.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+use trie::DomainLookup;
+use trie::gen_seed::*;
+use criterion::{Criterion, Bencher, ParameterizedBenchmark};
+
+const HOSTS: [&[u8]; 4] = [
+  b"washtucna.obeliskoide.org",
+  b"WashTucna.Obeliskoide.org",
+  b"test.alldomains.org",
+  b"washtucna.obeliskoide.com",
+];
+
+fn lookups<T: DomainLookup<u8>>(root: &T) {
+  for host in HOSTS.iter() {
+    root.domain_lookup(host);
+  }
+}
+
+/// seeds the trie, then checks that looking up known, wildcard and
+/// unknown hosts does not allocate before benchmarking those lookups
+fn lookup<T: DomainLookup<u8>>(root: &mut T, b: &mut Bencher, nb_elem_seed: i32) {
+  seed_bench_trie(root, nb_elem_seed);
+  seed_known_domain(root);
+  root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 5);
+
+  let before = ALLOCATIONS.load(Ordering::SeqCst);
+  lookups(root);
+  let allocations = ALLOCATIONS.load(Ordering::SeqCst) - before;
+  assert_eq!(allocations, 0, "{} allocations for {} lookups", allocations, HOSTS.len());
+
+  b.iter(|| lookups(root))
+}
+
+fn bench_allocations(c: &mut Criterion) {
+    let nb_elems_seed = 1000i32;
+
+    c.bench(
+      "allocation free lookups",
+      ParameterizedBenchmark::new("sozu", |b, n| {
+        let mut root: trie::sozu_trie::TrieNode<u8> = trie::sozu_trie::TrieNode::root();
+        lookup(&mut root, b, *n);
+      }, vec![nb_elems_seed])
+      .with_function("exp1", |b, n| {
+        let mut root: trie::experiment1_trie::TrieNode<u8> = trie::experiment1_trie::TrieNode::root();
+        lookup(&mut root, b, *n);
+      })
+      .with_function("exp2", |b, n| {
+        let mut root: trie::experiment2_trie::TrieNode<u8> = trie::experiment2_trie::TrieNode::root();
+        lookup(&mut root, b, *n);
+      })
+      .with_function("exp3", |b, n| {
+        let mut root: trie::experiment3_trie::TrieNode<u8> = trie::experiment3_trie::TrieNode::root();
+        lookup(&mut root, b, *n);
+      })
+      .with_function("linear", |b, n| {
+        let mut root: trie::linear::List<u8> = trie::linear::List::root();
+        lookup(&mut root, b, *n);
+      })
+    );
+}
+
+criterion_group!(allocations, bench_allocations);
+criterion_main!(allocations);
@@ -0,0 +1,97 @@
+#![feature(test)]
+extern crate trie;
+#[macro_use]
+extern crate criterion;
+extern crate jemallocator;
+
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+use trie::DomainLookup;
+use trie::gen_seed::*;
+use criterion::{Criterion, ParameterizedBenchmark};
+
+/// insert every domain, then remove all of them, one at a time
+fn fill_drain<T: DomainLookup<u8>>(root: &mut T, domains: &[(Vec<u8>, u8)]) {
+  for (domain, value) in domains.iter() {
+    root.domain_insert(domain.clone(), *value);
+  }
+
+  for (domain, _) in domains.iter() {
+    root.domain_remove(domain);
+  }
+}
+
+fn bench_fill_drain(c: &mut Criterion) {
+    let nb_elems_seed = 100i32;
+
+    c.bench(
+      "agg:filling and draining tree",
+      ParameterizedBenchmark::new("exp 1", |b, n| {
+          let domains = gen_bench_domains(*n);
+          b.iter(|| {
+            let mut root: trie::experiment1_trie::TrieNode<u8> = trie::experiment1_trie::TrieNode::root();
+            fill_drain(&mut root, &domains);
+          })
+        }, vec![nb_elems_seed])
+      .with_function("exp2", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::experiment2_trie::TrieNode<u8> = trie::experiment2_trie::TrieNode::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("exp3", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::experiment3_trie::TrieNode<u8> = trie::experiment3_trie::TrieNode::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("exp5", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::experiment5_trie_bitvec::TrieNode<u8> = trie::experiment5_trie_bitvec::TrieNode::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("exp8", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::experiment8_trie_cursor::TrieNode<u8> = trie::experiment8_trie_cursor::TrieNode::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("exp9", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::experiment9_hashmap::TrieNode<u8> = trie::experiment9_hashmap::TrieNode::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("exp10", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::experiment9_hashmap::TrieNode<u8, trie::experiment9_hashmap::SipHash> = trie::experiment9_hashmap::TrieNode::root_random();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("sozu", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::sozu_trie::TrieNode<u8> = trie::sozu_trie::TrieNode::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+      .with_function("linear", |b, n| {
+        let domains = gen_bench_domains(*n);
+        b.iter(|| {
+          let mut root: trie::linear::List<u8> = trie::linear::List::root();
+          fill_drain(&mut root, &domains);
+        })
+      })
+    );
+}
+
+criterion_group!(draining, bench_fill_drain);
+criterion_main!(draining);
@@ -37,7 +37,7 @@ fn bench_fill(c: &mut Criterion) {
         let mut root: trie::experiment4_fst::Machine<u8> = trie::experiment4_fst::Machine::new();
         seed_bench_trie(&mut root, *n);
         seed_known_domain(&mut root);
-        root.finish();
+        root.finish().unwrap();
       }))
       .with_function("exp5", |b, n| b.iter(|| {
         let mut root: trie::experiment5_trie_bitvec::TrieNode<u8> = trie::experiment5_trie_bitvec::TrieNode::root();
@@ -48,7 +48,7 @@ fn bench_fill(c: &mut Criterion) {
         let mut root: trie::experiment6_fst_bitvec::Machine<u8> = trie::experiment6_fst_bitvec::Machine::new();
         seed_bench_trie(&mut root, *n);
         seed_known_domain(&mut root);
-        root.finish();
+        root.finish().unwrap();
       }))
       .with_function("exp7", |b, n| b.iter(|| {
         let mut root: trie::experiment7_regexset::Machine<u8> = trie::experiment7_regexset::Machine::new();
@@ -67,9 +67,39 @@ fn bench_fill(c: &mut Criterion) {
         seed_known_domain(&mut root);
       }))
       .with_function("exp10", |b, n| b.iter(|| {
-        let mut root: trie::experiment10_hashmap_siphash::TrieNode<u8> = trie::experiment10_hashmap_siphash::TrieNode::root();
+        let mut root: trie::experiment9_hashmap::TrieNode<u8, trie::experiment9_hashmap::SipHash> = trie::experiment9_hashmap::TrieNode::root_random();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+      }))
+      .with_function("exp11", |b, n| b.iter(|| {
+        let mut root: trie::experiment11_art::Art<u8> = trie::experiment11_art::Art::root();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+      }))
+      .with_function("exp12", |b, n| b.iter(|| {
+        let mut root: trie::experiment12_double_array::DoubleArray<u8> = trie::experiment12_double_array::DoubleArray::root();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+      }))
+      .with_function("exp12 build", |b, n| b.iter(|| {
+        let (mut root, _) = trie::experiment12_double_array::DoubleArray::build(gen_bench_domains(*n));
+        seed_known_domain(&mut root);
+      }))
+      .with_function("exp13", |b, n| b.iter(|| {
+        let mut root: trie::experiment13_burst_trie::BurstTrie<u8> = trie::experiment13_burst_trie::BurstTrie::root();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+      }))
+      .with_function("exp14", |b, n| b.iter(|| {
+        let mut root: trie::experiment14_interned::InternedTrie<u8> = trie::experiment14_interned::InternedTrie::root();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+      }))
+      .with_function("exp15", |b, n| b.iter(|| {
+        let mut root: trie::experiment15_louds::LoudsTrie<u8> = trie::experiment15_louds::LoudsTrie::root();
         seed_bench_trie(&mut root, *n);
         seed_known_domain(&mut root);
+        root.compact();
       }))
       .with_function("sozu", |b, n| b.iter(|| {
         let mut root: trie::sozu_trie::TrieNode<u8> = trie::sozu_trie::TrieNode::root();
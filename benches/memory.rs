@@ -0,0 +1,133 @@
+#![feature(test)]
+extern crate trie;
+#[macro_use]
+extern crate criterion;
+extern crate jemallocator;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// jemalloc, keeping the number of bytes currently allocated through it
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+    jemallocator::Jemalloc.alloc(layout)
+  }
+
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    jemallocator::Jemalloc.dealloc(ptr, layout)
+  }
+
+  unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+    ALLOCATED.fetch_add(new_size, Ordering::SeqCst);
+    ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    jemallocator::Jemalloc.realloc(ptr, layout, new_size)
+  }
+}
+
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+use trie::{DomainLookup, InsertResult};
+use trie::gen_seed::*;
+use criterion::{Criterion, Bencher, ParameterizedBenchmark};
+
+/// `seed_bench_trie` generates about 10 routes per seed element: 1k, 10k and 100k routes
+const SEEDS: [i32; 3] = [100, 1000, 10000];
+
+/// bytes allocated by the structure filled with the bench domains, per route
+fn bytes_per_route<T: DomainLookup<u8>, F: Fn() -> T>(new: F, nb_elems_seed: i32) -> (usize, usize) {
+  let domains = gen_bench_domains(nb_elems_seed);
+
+  let before = ALLOCATED.load(Ordering::SeqCst);
+  let mut root = new();
+  let mut routes = 0;
+  // the keys are copied, so their allocations are counted
+  for &(ref key, value) in domains.iter() {
+    if root.domain_insert(key.clone(), value) == InsertResult::Ok {
+      routes += 1;
+    }
+  }
+  let bytes = ALLOCATED.load(Ordering::SeqCst) - before;
+
+  (routes, bytes / routes)
+}
+
+fn report<T: DomainLookup<u8>, F: Fn() -> T>(name: &str, new: F) {
+  for &n in SEEDS.iter() {
+    let (routes, bytes) = bytes_per_route(&new, n);
+    println!("{:<6} {:>7} routes: {:>5} bytes per route", name, routes, bytes);
+  }
+}
+
+/// exp15 cannot be filled one route at a time: it is built from the entries
+/// of an exp3 trie, and its `size()` is compared with that trie's
+fn report_louds() {
+  for &n in SEEDS.iter() {
+    let mut exp3 = trie::experiment3_trie::TrieNode::root();
+    for (key, value) in gen_bench_domains(n) {
+      exp3.domain_insert(key, value);
+    }
+
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let (root, _) = trie::experiment15_louds::LoudsTrie::from_entries(exp3.iter());
+    let bytes = ALLOCATED.load(Ordering::SeqCst) - before;
+
+    let routes = root.len();
+    println!("{:<6} {:>7} routes: {:>5} bytes per route, size() per route: {:>5} (exp3: {:>5})",
+      "exp15", routes, bytes / routes, root.size() / routes, exp3.size() / routes);
+  }
+}
+
+fn lookup<T: DomainLookup<u8>>(root: &mut T, b: &mut Bencher, nb_elem_seed: i32) {
+  seed_bench_trie(root, nb_elem_seed);
+  seed_known_domain(root);
+
+  b.iter(|| {
+    root.domain_lookup(b"washtucna.obeliskoide.org");
+  })
+}
+
+fn bench_memory(c: &mut Criterion) {
+    report("sozu", trie::sozu_trie::TrieNode::<u8>::root);
+    report("exp3", trie::experiment3_trie::TrieNode::<u8>::root);
+    report("exp9", trie::experiment9_hashmap::TrieNode::<u8>::root);
+    report("exp11", trie::experiment11_art::Art::<u8>::root);
+    report("exp12", trie::experiment12_double_array::DoubleArray::<u8>::root);
+    report("exp13", trie::experiment13_burst_trie::BurstTrie::<u8>::root);
+    report_louds();
+
+    c.bench(
+      "agg:registered domains by route count",
+      ParameterizedBenchmark::new("exp3", |b, n| {
+        let mut root: trie::experiment3_trie::TrieNode<u8> = trie::experiment3_trie::TrieNode::root();
+        lookup(&mut root, b, *n);
+      }, SEEDS.to_vec())
+      .with_function("exp9", |b, n| {
+        let mut root: trie::experiment9_hashmap::TrieNode<u8> = trie::experiment9_hashmap::TrieNode::root();
+        lookup(&mut root, b, *n);
+      })
+      .with_function("exp13", |b, n| {
+        let mut root: trie::experiment13_burst_trie::BurstTrie<u8> = trie::experiment13_burst_trie::BurstTrie::root();
+        lookup(&mut root, b, *n);
+      })
+      .with_function("exp15", |b, n| {
+        let mut root: trie::experiment15_louds::LoudsTrie<u8> = trie::experiment15_louds::LoudsTrie::root();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+        root.compact();
+
+        b.iter(|| {
+          root.domain_lookup(b"washtucna.obeliskoide.org");
+        });
+      })
+    );
+}
+
+criterion_group!(memory, bench_memory);
+criterion_main!(memory);
@@ -0,0 +1,58 @@
+#![feature(test)]
+extern crate trie;
+#[macro_use]
+extern crate criterion;
+extern crate jemallocator;
+
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+use trie::DomainLookup;
+use trie::gen_seed::*;
+use trie::experiment4_fst::Machine;
+use criterion::{Criterion, ParameterizedBenchmark};
+
+/// finished fst seeded with the usual 1000 elements, with `overlay_size`
+/// additional domains waiting in the overlay
+fn machine_with_overlay(overlay_size: usize) -> Machine<u8> {
+  let mut root: Machine<u8> = Machine::new();
+  seed_bench_trie(&mut root, 1000);
+  seed_known_domain(&mut root);
+  root.finish().unwrap();
+
+  for _ in 0..overlay_size {
+    root.domain_insert(gen_uuid_seed_domain("com"), 1);
+  }
+
+  root
+}
+
+fn bench_overlay(c: &mut Criterion) {
+    c.bench(
+      "exp4 overlay",
+      ParameterizedBenchmark::new("lookup with overlay", |b, n| {
+          let root = machine_with_overlay(*n);
+          b.iter(|| {
+            root.domain_lookup(b"washtucna.obeliskoide.org");
+            root.domain_lookup(b"washtucna.obeliskoide.com")
+          })
+        }, vec![0usize, 10, 100, 1000, 10000])
+      .with_function("lookup after compact", |b, n| {
+        let mut root = machine_with_overlay(*n);
+        root.compact().unwrap();
+        b.iter(|| {
+          root.domain_lookup(b"washtucna.obeliskoide.org");
+          root.domain_lookup(b"washtucna.obeliskoide.com")
+        })
+      })
+      .with_function("compact", |b, n| {
+        b.iter_with_setup(|| machine_with_overlay(*n), |mut root| {
+          root.compact().unwrap();
+          root
+        })
+      })
+    );
+}
+
+criterion_group!(overlay, bench_overlay);
+criterion_main!(overlay);
@@ -0,0 +1,85 @@
+#![feature(test)]
+extern crate trie;
+#[macro_use]
+extern crate criterion;
+extern crate jemallocator;
+
+#[global_allocator]
+static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+use trie::DomainLookup;
+use trie::gen_seed::*;
+use criterion::{Criterion, Bencher, ParameterizedBenchmark};
+
+/// hostnames of the application that gets deleted
+fn application_domains() -> Vec<Vec<u8>> {
+  let mut v: Vec<Vec<u8>> = (0..100).map(|i| format!("app{}.example.com", i).into_bytes()).collect();
+  v.push(Vec::from(&b"example.com"[..]));
+  v.push(Vec::from(&b"*.example.com"[..]));
+  v
+}
+
+fn add_application<T: DomainLookup<u8>>(root: &mut T, domains: &[Vec<u8>]) {
+  for domain in domains.iter() {
+    root.domain_insert(domain.clone(), 3);
+  }
+}
+
+/// every iteration adds the application to the seeded structure then deletes
+/// it, so both versions pay for the same inserts
+fn remove_loop<T: DomainLookup<u8>>(root: &mut T, b: &mut Bencher, nb_elem_seed: i32) {
+  let domains = application_domains();
+  seed_bench_trie(root, nb_elem_seed);
+
+  b.iter(|| {
+    add_application(root, &domains);
+    for domain in domains.iter() {
+      root.domain_remove(domain);
+    }
+  })
+}
+
+macro_rules! remove_subtree {
+  ($root:expr, $b:expr, $n:expr) => {{
+    let mut root = $root;
+    let domains = application_domains();
+    seed_bench_trie(&mut root, $n);
+
+    $b.iter(|| {
+      add_application(&mut root, &domains);
+      root.remove_subtree(&b"example.com"[..])
+    })
+  }};
+}
+
+fn bench_remove_subtree(c: &mut Criterion) {
+    let nb_elems_seed = 10000i32;
+
+    c.bench(
+      "removing an application",
+      ParameterizedBenchmark::new("sozu remove loop", |b, n| {
+        let mut root: trie::sozu_trie::TrieNode<u8> = trie::sozu_trie::TrieNode::root();
+        remove_loop(&mut root, b, *n);
+      }, vec![nb_elems_seed])
+      .with_function("sozu remove_subtree", |b, n| {
+        remove_subtree!(trie::sozu_trie::TrieNode::<u8>::root(), b, *n);
+      })
+      .with_function("exp3 remove loop", |b, n| {
+        let mut root: trie::experiment3_trie::TrieNode<u8> = trie::experiment3_trie::TrieNode::root();
+        remove_loop(&mut root, b, *n);
+      })
+      .with_function("exp3 remove_subtree", |b, n| {
+        remove_subtree!(trie::experiment3_trie::TrieNode::<u8>::root(), b, *n);
+      })
+      .with_function("exp9 remove loop", |b, n| {
+        let mut root: trie::experiment9_hashmap::TrieNode<u8> = trie::experiment9_hashmap::TrieNode::root();
+        remove_loop(&mut root, b, *n);
+      })
+      .with_function("exp9 remove_subtree", |b, n| {
+        remove_subtree!(trie::experiment9_hashmap::TrieNode::<u8>::root(), b, *n);
+      })
+    );
+}
+
+criterion_group!(removing, bench_remove_subtree);
+criterion_main!(removing);
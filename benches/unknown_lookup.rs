@@ -52,7 +52,7 @@ fn bench_lookup(c: &mut Criterion) {
         let mut root: trie::experiment4_fst::Machine<u8> = trie::experiment4_fst::Machine::new();
         seed_bench_trie(&mut root, *n);
         seed_known_domain(&mut root);
-        root.finish();
+        root.finish().unwrap();
 
         b.iter(|| {
           root.domain_lookup(b"sozu.org");
@@ -95,10 +95,36 @@ fn bench_lookup(c: &mut Criterion) {
         //println!("exp3 byte size: {}", root.size());
       })
       .with_function("exp10", |mut b, n| {
-        let mut root: trie::experiment10_hashmap_siphash::TrieNode<u8> = trie::experiment10_hashmap_siphash::TrieNode::root();
+        let mut root: trie::experiment9_hashmap::TrieNode<u8, trie::experiment9_hashmap::SipHash> = trie::experiment9_hashmap::TrieNode::root_random();
         lookup(root, &mut b, *n);
         //println!("exp3 byte size: {}", root.size());
       })
+      .with_function("exp11", |mut b, n| {
+        let root: trie::experiment11_art::Art<u8> = trie::experiment11_art::Art::root();
+        lookup(root, &mut b, *n);
+      })
+      .with_function("exp12", |mut b, n| {
+        let root: trie::experiment12_double_array::DoubleArray<u8> = trie::experiment12_double_array::DoubleArray::root();
+        lookup(root, &mut b, *n);
+      })
+      .with_function("exp13", |mut b, n| {
+        let root: trie::experiment13_burst_trie::BurstTrie<u8> = trie::experiment13_burst_trie::BurstTrie::root();
+        lookup(root, &mut b, *n);
+      })
+      .with_function("exp14", |mut b, n| {
+        let root: trie::experiment14_interned::InternedTrie<u8> = trie::experiment14_interned::InternedTrie::root();
+        lookup(root, &mut b, *n);
+      })
+      .with_function("exp15", |b, n| {
+        let mut root: trie::experiment15_louds::LoudsTrie<u8> = trie::experiment15_louds::LoudsTrie::root();
+        seed_bench_trie(&mut root, *n);
+        seed_known_domain(&mut root);
+        root.compact();
+
+        b.iter(|| {
+          root.domain_lookup(b"sozu.org");
+        });
+      })
       .with_function("exp5", |mut b, n| {
         let root: trie::experiment5_trie_bitvec::TrieNode<u8> = trie::experiment5_trie_bitvec::TrieNode::root();
         lookup(root, &mut b, *n);
@@ -107,7 +133,7 @@ fn bench_lookup(c: &mut Criterion) {
         let mut root: trie::experiment6_fst_bitvec::Machine<u8> = trie::experiment6_fst_bitvec::Machine::new();
         seed_bench_trie(&mut root, *n);
         seed_known_domain(&mut root);
-        root.finish();
+        root.finish().unwrap();
 
         b.iter(|| {
           root.domain_lookup(b"sozu.org");
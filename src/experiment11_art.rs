@@ -0,0 +1,855 @@
+//! adaptive radix tree
+//!
+//! like the tries, this works on reversed domain names, but each inner node
+//! picks the smallest of four layouts for its children:
+//! - Node4 and Node16: sorted arrays of key bytes, next to the children
+//! - Node48: a 256 bytes index pointing to up to 48 children
+//! - Node256: one slot per byte
+//!
+//! a node grows to the next layout when it is full, and shrinks back when
+//! removals leave it mostly empty. Inner nodes store the bytes shared by all
+//! the keys under them (path compression), and a key that is alone in its
+//! subtree is stored in a leaf, without inner nodes for its remaining bytes
+//! (lazy expansion).
+//!
+//! keys are not reversed in memory: the byte at depth `i` is read from the end
+//! of the key, so lookups walk the host without copying it. Wildcards match
+//! like in the sozu trie: the host is looked up again with its first label
+//! replaced by `*`, then with each of its parent domains followed by `.**`
+
+use std::{cmp,iter,mem,str};
+use std::fmt::Debug;
+
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, specificity, is_under, validate_key};
+
+/// byte of the reversed key at this depth
+fn key_at(key: &[u8], depth: usize) -> u8 {
+  key[key.len() - 1 - depth]
+}
+
+/// a reversed key as the tree reads it: the last `len` bytes of the host,
+/// from its end and lowercased, followed by `tail`
+struct View<'a> {
+  host: &'a [u8],
+  len:  usize,
+  tail: &'static [u8],
+}
+
+impl<'a> View<'a> {
+  fn new(host: &'a [u8], len: usize, tail: &'static [u8]) -> View<'a> {
+    View { host, len, tail }
+  }
+
+  fn len(&self) -> usize {
+    self.len + self.tail.len()
+  }
+
+  fn at(&self, depth: usize) -> u8 {
+    if depth < self.len {
+      self.host[self.host.len() - 1 - depth].to_ascii_lowercase()
+    } else {
+      self.tail[depth - self.len]
+    }
+  }
+
+  /// true if the key stored in a leaf is this one. The bytes before `depth`
+  /// were matched on the way down
+  fn matches(&self, key: &[u8], depth: usize) -> bool {
+    key.len() == self.len() && (depth..key.len()).all(|i| key_at(key, i) == self.at(i))
+  }
+}
+
+enum Children<V> {
+  Node4   { keys: [u8; 4],  nodes: Vec<Node<V>> },
+  Node16  { keys: [u8; 16], nodes: Vec<Node<V>> },
+  /// `index[byte]` is the position of the child in `nodes` plus one, 0 if there is none
+  Node48  { index: Box<[u8; 256]>, nodes: Vec<Node<V>> },
+  Node256 { nodes: Vec<Option<Node<V>>>, count: usize },
+}
+
+fn insert_sorted<V>(keys: &mut [u8], nodes: &mut Vec<Node<V>>, byte: u8, node: Node<V>) {
+  let len = nodes.len();
+  let pos = keys[..len].iter().position(|&k| k > byte).unwrap_or(len);
+  for i in (pos..len).rev() {
+    keys[i + 1] = keys[i];
+  }
+  keys[pos] = byte;
+  nodes.insert(pos, node);
+}
+
+fn remove_sorted<V>(keys: &mut [u8], nodes: &mut Vec<Node<V>>, byte: u8) -> Option<Node<V>> {
+  let len = nodes.len();
+  let pos = keys[..len].iter().position(|&k| k == byte)?;
+  for i in pos..len - 1 {
+    keys[i] = keys[i + 1];
+  }
+  Some(nodes.remove(pos))
+}
+
+impl<V> Children<V> {
+  fn new() -> Children<V> {
+    Children::Node4 { keys: [0; 4], nodes: Vec::new() }
+  }
+
+  /// the smallest layout holding these children, sorted by byte
+  fn from_pairs(pairs: Vec<(u8, Node<V>)>) -> Children<V> {
+    if pairs.len() <= 4 {
+      let mut keys = [0; 4];
+      for (i, &(byte, _)) in pairs.iter().enumerate() {
+        keys[i] = byte;
+      }
+      Children::Node4 { keys, nodes: pairs.into_iter().map(|(_, node)| node).collect() }
+    } else if pairs.len() <= 16 {
+      let mut keys = [0; 16];
+      for (i, &(byte, _)) in pairs.iter().enumerate() {
+        keys[i] = byte;
+      }
+      Children::Node16 { keys, nodes: pairs.into_iter().map(|(_, node)| node).collect() }
+    } else if pairs.len() <= 48 {
+      let mut index = Box::new([0; 256]);
+      for (i, &(byte, _)) in pairs.iter().enumerate() {
+        index[byte as usize] = i as u8 + 1;
+      }
+      Children::Node48 { index, nodes: pairs.into_iter().map(|(_, node)| node).collect() }
+    } else {
+      let count = pairs.len();
+      let mut nodes: Vec<Option<Node<V>>> = (0..256).map(|_| None).collect();
+      for (byte, node) in pairs {
+        nodes[byte as usize] = Some(node);
+      }
+      Children::Node256 { nodes, count }
+    }
+  }
+
+  /// the children sorted by byte
+  fn into_pairs(self) -> Vec<(u8, Node<V>)> {
+    match self {
+      Children::Node4 { keys, nodes }  => keys.iter().cloned().zip(nodes).collect(),
+      Children::Node16 { keys, nodes } => keys.iter().cloned().zip(nodes).collect(),
+      Children::Node48 { index, nodes } => {
+        let mut nodes: Vec<Option<Node<V>>> = nodes.into_iter().map(Some).collect();
+        (0..256).filter(|&byte| index[byte] != 0)
+          .map(|byte| (byte as u8, nodes[index[byte] as usize - 1].take().unwrap()))
+          .collect()
+      },
+      Children::Node256 { nodes, .. } => nodes.into_iter().enumerate()
+        .filter_map(|(byte, node)| node.map(|node| (byte as u8, node)))
+        .collect(),
+    }
+  }
+
+  fn len(&self) -> usize {
+    match *self {
+      Children::Node4 { ref nodes, .. } | Children::Node16 { ref nodes, .. } |
+        Children::Node48 { ref nodes, .. } => nodes.len(),
+      Children::Node256 { count, .. } => count,
+    }
+  }
+
+  fn kind(&self) -> &'static str {
+    match *self {
+      Children::Node4 { .. }   => "Node4",
+      Children::Node16 { .. }  => "Node16",
+      Children::Node48 { .. }  => "Node48",
+      Children::Node256 { .. } => "Node256",
+    }
+  }
+
+  fn get(&self, byte: u8) -> Option<&Node<V>> {
+    match *self {
+      Children::Node4 { ref keys, ref nodes } => {
+        keys[..nodes.len()].iter().position(|&k| k == byte).map(|i| &nodes[i])
+      },
+      Children::Node16 { ref keys, ref nodes } => {
+        keys[..nodes.len()].binary_search(&byte).ok().map(|i| &nodes[i])
+      },
+      Children::Node48 { ref index, ref nodes } => match index[byte as usize] {
+        0    => None,
+        slot => Some(&nodes[slot as usize - 1]),
+      },
+      Children::Node256 { ref nodes, .. } => nodes[byte as usize].as_ref(),
+    }
+  }
+
+  fn get_mut(&mut self, byte: u8) -> Option<&mut Node<V>> {
+    match *self {
+      Children::Node4 { ref keys, ref mut nodes } => {
+        keys[..nodes.len()].iter().position(|&k| k == byte).map(move |i| &mut nodes[i])
+      },
+      Children::Node16 { ref keys, ref mut nodes } => {
+        keys[..nodes.len()].binary_search(&byte).ok().map(move |i| &mut nodes[i])
+      },
+      Children::Node48 { ref index, ref mut nodes } => match index[byte as usize] {
+        0    => None,
+        slot => Some(&mut nodes[slot as usize - 1]),
+      },
+      Children::Node256 { ref mut nodes, .. } => nodes[byte as usize].as_mut(),
+    }
+  }
+
+  /// children in byte order
+  fn nodes(&self) -> Vec<&Node<V>> {
+    match *self {
+      Children::Node4 { ref nodes, .. } | Children::Node16 { ref nodes, .. } => nodes.iter().collect(),
+      Children::Node48 { ref index, ref nodes } => {
+        index.iter().filter(|&&slot| slot != 0).map(|&slot| &nodes[slot as usize - 1]).collect()
+      },
+      Children::Node256 { ref nodes, .. } => nodes.iter().filter_map(|node| node.as_ref()).collect(),
+    }
+  }
+
+  /// adds a child for a byte that has none, growing to the next layout if full
+  fn insert(&mut self, byte: u8, node: Node<V>) {
+    let full = match *self {
+      Children::Node4 { ref nodes, .. }  => nodes.len() == 4,
+      Children::Node16 { ref nodes, .. } => nodes.len() == 16,
+      Children::Node48 { ref nodes, .. } => nodes.len() == 48,
+      Children::Node256 { .. }           => false,
+    };
+
+    if full {
+      let mut pairs = mem::replace(self, Children::new()).into_pairs();
+      let pos = pairs.iter().position(|&(k, _)| k > byte).unwrap_or(pairs.len());
+      pairs.insert(pos, (byte, node));
+      *self = Children::from_pairs(pairs);
+      return;
+    }
+
+    match *self {
+      Children::Node4 { ref mut keys, ref mut nodes }  => insert_sorted(&mut keys[..], nodes, byte, node),
+      Children::Node16 { ref mut keys, ref mut nodes } => insert_sorted(&mut keys[..], nodes, byte, node),
+      Children::Node48 { ref mut index, ref mut nodes } => {
+        nodes.push(node);
+        index[byte as usize] = nodes.len() as u8;
+      },
+      Children::Node256 { ref mut nodes, ref mut count } => {
+        nodes[byte as usize] = Some(node);
+        *count += 1;
+      },
+    }
+  }
+
+  /// removes the child for this byte, shrinking to the previous layout when
+  /// the node gets mostly empty
+  fn remove(&mut self, byte: u8) -> Option<Node<V>> {
+    let node = match *self {
+      Children::Node4 { ref mut keys, ref mut nodes }  => remove_sorted(&mut keys[..], nodes, byte),
+      Children::Node16 { ref mut keys, ref mut nodes } => remove_sorted(&mut keys[..], nodes, byte),
+      Children::Node48 { ref mut index, ref mut nodes } => match index[byte as usize] {
+        0    => None,
+        slot => {
+          let last = nodes.len() as u8;
+          index[byte as usize] = 0;
+          let node = nodes.swap_remove(slot as usize - 1);
+          // the last child moved to the free slot
+          if slot != last {
+            if let Some(moved) = index.iter().position(|&s| s == last) {
+              index[moved] = slot;
+            }
+          }
+          Some(node)
+        },
+      },
+      Children::Node256 { ref mut nodes, ref mut count } => {
+        let node = nodes[byte as usize].take();
+        if node.is_some() {
+          *count -= 1;
+        }
+        node
+      },
+    };
+
+    let shrink = match *self {
+      Children::Node4 { .. }             => false,
+      Children::Node16 { ref nodes, .. } => nodes.len() <= 3,
+      Children::Node48 { ref nodes, .. } => nodes.len() <= 12,
+      Children::Node256 { count, .. }    => count <= 37,
+    };
+
+    if shrink {
+      let pairs = mem::replace(self, Children::new()).into_pairs();
+      *self = Children::from_pairs(pairs);
+    }
+
+    node
+  }
+}
+
+struct Inner<V> {
+  /// path compression: the bytes shared by every key under this node
+  prefix:    Key,
+  /// the key ending at this node, like `example.com` above `www.example.com`
+  key_value: Option<KeyValue<Key,V>>,
+  children:  Children<V>,
+}
+
+enum Node<V> {
+  /// lazy expansion: a key alone in its subtree is stored whole
+  Leaf(KeyValue<Key,V>),
+  Inner(Box<Inner<V>>),
+}
+
+impl<V> Inner<V> {
+  fn new(prefix: Key) -> Inner<V> {
+    Inner {
+      prefix,
+      key_value: None,
+      children:  Children::new(),
+    }
+  }
+
+  fn matches_prefix(&self, view: &View, depth: usize) -> bool {
+    depth + self.prefix.len() <= view.len() &&
+      self.prefix.iter().enumerate().all(|(i, &c)| view.at(depth + i) == c)
+  }
+
+  /// stores the key below this node, whose prefix ends at `depth`
+  fn attach(&mut self, key: Key, value: V, depth: usize) {
+    if key.len() == depth {
+      self.key_value = Some((key, value));
+    } else {
+      let byte = key_at(&key, depth);
+      self.children.insert(byte, Node::Leaf((key, value)));
+    }
+  }
+
+  /// the compressed path stops after `at` bytes, the rest of the prefix
+  /// moves to a new child with the entry and children of this node
+  fn split(&mut self, at: usize) {
+    let rest = self.prefix.split_off(at + 1);
+    let byte = self.prefix.pop().unwrap();
+    let child = Inner {
+      prefix:    rest,
+      key_value: self.key_value.take(),
+      children:  mem::replace(&mut self.children, Children::new()),
+    };
+    self.children.insert(byte, Node::Inner(Box::new(child)));
+  }
+
+  fn insert(&mut self, key: Key, value: V, depth: usize) -> InsertResult {
+    let matched = self.prefix.iter().enumerate()
+      .take_while(|&(i, &c)| depth + i < key.len() && key_at(&key, depth + i) == c)
+      .count();
+    if matched < self.prefix.len() {
+      self.split(matched);
+    }
+
+    let depth = depth + self.prefix.len();
+    if depth == key.len() {
+      if self.key_value.is_some() {
+        return InsertResult::Existing;
+      }
+      self.key_value = Some((key, value));
+      return InsertResult::Ok;
+    }
+
+    if let Some(child) = self.children.get_mut(key_at(&key, depth)) {
+      return child.insert(key, value, depth + 1);
+    }
+
+    self.attach(key, value, depth);
+    InsertResult::Ok
+  }
+
+  /// a node without children, or with one child and no entry, does not
+  /// need to exist
+  fn is_sparse(&self) -> bool {
+    self.children.len() == 0 || (self.children.len() == 1 && self.key_value.is_none())
+  }
+
+  /// replaces a sparse node with its entry, or merges it with its only child
+  fn collapse(mut inner: Box<Inner<V>>) -> Option<Node<V>> {
+    if !inner.is_sparse() {
+      return Some(Node::Inner(inner));
+    }
+
+    if inner.children.len() == 0 {
+      return inner.key_value.take().map(Node::Leaf);
+    }
+
+    let (byte, child) = mem::replace(&mut inner.children, Children::new()).into_pairs().pop().unwrap();
+    match child {
+      Node::Leaf(kv) => Some(Node::Leaf(kv)),
+      Node::Inner(mut child) => {
+        inner.prefix.push(byte);
+        inner.prefix.append(&mut child.prefix);
+        child.prefix = mem::take(&mut inner.prefix);
+        Some(Node::Inner(child))
+      },
+    }
+  }
+
+  fn remove(&mut self, view: &View, depth: usize) -> Option<KeyValue<Key,V>> {
+    if !self.matches_prefix(view, depth) {
+      return None;
+    }
+
+    let depth = depth + self.prefix.len();
+    if depth == view.len() {
+      return self.key_value.take();
+    }
+
+    let byte = view.at(depth);
+    let removed = match *self.children.get_mut(byte)? {
+      // the leaf is detached below
+      Node::Leaf(ref kv) if view.matches(&kv.0, depth + 1) => None,
+      Node::Leaf(_) => return None,
+      Node::Inner(ref mut child) => {
+        let removed = child.remove(view, depth + 1);
+        if removed.is_none() || !child.is_sparse() {
+          return removed;
+        }
+        removed
+      },
+    };
+
+    match self.children.remove(byte) {
+      Some(Node::Leaf(kv)) => Some(kv),
+      Some(Node::Inner(child)) => {
+        if let Some(node) = Inner::collapse(child) {
+          self.children.insert(byte, node);
+        }
+        removed
+      },
+      None => None,
+    }
+  }
+}
+
+impl<V> Node<V> {
+  fn insert(&mut self, key: Key, value: V, depth: usize) -> InsertResult {
+    let common = match *self {
+      Node::Inner(ref mut inner) => return inner.insert(key, value, depth),
+      Node::Leaf(ref kv) if kv.0 == key => return InsertResult::Existing,
+      Node::Leaf(ref kv) => depth + (depth..cmp::min(kv.0.len(), key.len()))
+        .take_while(|&i| key_at(&kv.0, i) == key_at(&key, i))
+        .count(),
+    };
+
+    // the leaf and the new key share an inner node for their common bytes
+    let mut inner = Box::new(Inner::new((depth..common).map(|i| key_at(&key, i)).collect()));
+    inner.attach(key, value, common);
+    if let Node::Leaf((key, value)) = mem::replace(self, Node::Inner(inner)) {
+      if let Node::Inner(ref mut inner) = *self {
+        inner.attach(key, value, common);
+      }
+    }
+
+    InsertResult::Ok
+  }
+
+  fn find(&self, view: &View, depth: usize) -> Option<&KeyValue<Key,V>> {
+    let mut node = self;
+    let mut depth = depth;
+    loop {
+      let inner = match *node {
+        Node::Leaf(ref kv) => return if view.matches(&kv.0, depth) { Some(kv) } else { None },
+        Node::Inner(ref inner) => inner,
+      };
+
+      if !inner.matches_prefix(view, depth) {
+        return None;
+      }
+
+      depth += inner.prefix.len();
+      if depth == view.len() {
+        return inner.key_value.as_ref();
+      }
+
+      node = inner.children.get(view.at(depth))?;
+      depth += 1;
+    }
+  }
+
+  fn find_mut(&mut self, view: &View, depth: usize) -> Option<&mut V> {
+    match *self {
+      Node::Leaf(ref mut kv) => if view.matches(&kv.0, depth) { Some(&mut kv.1) } else { None },
+      Node::Inner(ref mut inner) => {
+        if !inner.matches_prefix(view, depth) {
+          return None;
+        }
+
+        let depth = depth + inner.prefix.len();
+        if depth == view.len() {
+          return inner.key_value.as_mut().map(|kv| &mut kv.1);
+        }
+
+        inner.children.get_mut(view.at(depth))?.find_mut(view, depth + 1)
+      },
+    }
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    match *self {
+      Node::Leaf(ref kv) => entries.push(kv),
+      Node::Inner(ref inner) => {
+        entries.extend(inner.key_value.as_ref());
+        for child in inner.children.nodes() {
+          child.collect(entries);
+        }
+      },
+    }
+  }
+
+  // the domain is matched like a host, then its whole subtree is collected
+  fn collect_under<'a>(&'a self, domain: &View, depth: usize, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    let inner = match *self {
+      Node::Leaf(ref kv) => return entries.push(kv),
+      Node::Inner(ref inner) => inner,
+    };
+
+    let end = cmp::min(domain.len(), depth + inner.prefix.len());
+    if (depth..end).any(|i| domain.at(i) != inner.prefix[i - depth]) {
+      return;
+    }
+
+    let depth = depth + inner.prefix.len();
+    if domain.len() <= depth {
+      self.collect(entries);
+    } else if let Some(child) = inner.children.get(domain.at(depth)) {
+      child.collect_under(domain, depth + 1, entries);
+    }
+  }
+}
+
+impl<V: Debug> Node<V> {
+  fn print(&self, indent: usize) {
+    let prefix = " ".repeat(2 * indent);
+    match *self {
+      Node::Leaf((ref key, ref value)) => {
+        println!("{}leaf: ({},{:?})", prefix, str::from_utf8(key).unwrap(), value);
+      },
+      Node::Inner(ref inner) => {
+        match inner.key_value {
+          Some((ref key, ref value)) => println!("{}{} {}: ({},{:?})", prefix, inner.children.kind(),
+            str::from_utf8(&inner.prefix).unwrap(), str::from_utf8(key).unwrap(), value),
+          None => println!("{}{} {}: None", prefix, inner.children.kind(),
+            str::from_utf8(&inner.prefix).unwrap()),
+        }
+        for child in inner.children.nodes() {
+          child.print(indent + 1);
+        }
+      },
+    }
+  }
+}
+
+pub struct Art<V> {
+  root:   Option<Node<V>>,
+  /// number of exact keys, single label wildcards and deep wildcards, to
+  /// skip the wildcard lookups when there are none
+  counts: [usize; 3],
+}
+
+impl<V: Debug> Art<V> {
+  pub fn root() -> Art<V> {
+    Art {
+      root:   None,
+      counts: [0; 3],
+    }
+  }
+
+  /// inserts a lowercased key
+  pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
+    if key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
+
+    let rank = specificity(&key);
+    let res = match self.root {
+      None => {
+        self.root = Some(Node::Leaf((key, value)));
+        InsertResult::Ok
+      },
+      Some(ref mut root) => root.insert(key, value, 0),
+    };
+
+    if res == InsertResult::Ok {
+      self.counts[rank] += 1;
+    }
+    res
+  }
+
+  /// removes the key, and returns the entry
+  pub fn remove(&mut self, key: &[u8]) -> Option<KeyValue<Key,V>> {
+    let view = View::new(key, key.len(), b"");
+    let removed = match self.root.take() {
+      None => None,
+      Some(Node::Leaf(kv)) => {
+        if view.matches(&kv.0, 0) {
+          Some(kv)
+        } else {
+          self.root = Some(Node::Leaf(kv));
+          None
+        }
+      },
+      Some(Node::Inner(mut inner)) => {
+        let removed = inner.remove(&view, 0);
+        self.root = Inner::collapse(inner);
+        removed
+      },
+    };
+
+    if let Some(ref kv) = removed {
+      self.counts[specificity(&kv.0)] -= 1;
+    }
+    removed
+  }
+
+  fn get(&self, view: &View) -> Option<&KeyValue<Key,V>> {
+    self.root.as_ref().and_then(|root| root.find(view, 0))
+  }
+
+  /// calls `f` with the keys matching the host, from the most specific to
+  /// the least specific, until it returns false: the host itself, the single
+  /// label wildcard, then deep wildcards from the longest domain
+  fn for_each_match<'a, F>(&'a self, host: &[u8], mut f: F) where F: FnMut(&'a KeyValue<Key,V>) -> bool {
+    if host.is_empty() {
+      return;
+    }
+
+    if let Some(kv) = self.get(&View::new(host, host.len(), b"")) {
+      if !f(kv) {
+        return;
+      }
+    }
+
+    if self.counts[1] > 0 {
+      // a host without dots matches the `*` key
+      let len = host.iter().position(|&c| c == b'.').map(|dot| host.len() - dot).unwrap_or(0);
+      if let Some(kv) = self.get(&View::new(host, len, b"*")) {
+        if !f(kv) {
+          return;
+        }
+      }
+    }
+
+    if self.counts[2] > 0 {
+      let parents = host.iter().enumerate().filter(|&(_, &c)| c == b'.').map(|(i, _)| host.len() - i - 1);
+      for len in iter::once(host.len()).chain(parents) {
+        if let Some(kv) = self.get(&View::new(host, len, b".**")) {
+          if !f(kv) {
+            return;
+          }
+        }
+      }
+
+      if let Some(kv) = self.get(&View::new(host, 0, b"**")) {
+        f(kv);
+      }
+    }
+  }
+
+  /// every entry stored in the tree, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<'_, V> {
+    let mut entries = Vec::new();
+    if let Some(ref root) = self.root {
+      root.collect(&mut entries);
+    }
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains. They are stored under the
+  /// reversed domain, so only that subtree is visited
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<'_, V> {
+    let mut entries = Vec::new();
+    if let Some(ref root) = self.root {
+      root.collect_under(&View::new(domain, domain.len(), b""), 0, &mut entries);
+    }
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.counts.iter().sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.root.is_none()
+  }
+
+  pub fn print(&self) {
+    if let Some(ref root) = self.root {
+      root.print(0);
+    }
+  }
+}
+
+impl<V: Debug> DomainLookup<V> for Art<V> {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
+    self.insert(key, value)
+  }
+
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    self.remove(key).into()
+  }
+
+  fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let mut found = None;
+    self.for_each_match(key, |kv| {
+      found = Some(kv);
+      false
+    });
+    found
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let view = View::new(key, key.len(), b"");
+    self.root.as_mut().and_then(|root| root.find_mut(&view, 0))
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut matches = Vec::new();
+    self.for_each_match(key, |kv| {
+      matches.push(kv);
+      true
+    });
+    matches
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn kind<V>(root: &Art<V>) -> &'static str {
+    match root.root {
+      Some(Node::Inner(ref inner)) => inner.children.kind(),
+      _ => "Leaf",
+    }
+  }
+
+  #[test]
+  fn insert() {
+    let mut root: Art<u8> = Art::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    // a single key is a leaf
+    assert_eq!(kind(&root), "Leaf");
+    assert_eq!(root.insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 4), InsertResult::Existing);
+    root.print();
+
+    // the common bytes are stored once, in the root
+    match root.root {
+      Some(Node::Inner(ref inner)) => {
+        assert_eq!(&inner.prefix[..], &b"moc.elpmaxe"[..]);
+        assert_eq!(inner.key_value, Some((Vec::from(&b"example.com"[..]), 3)));
+        assert_eq!(inner.children.len(), 1);
+      },
+      _ => panic!("the root should be an inner node"),
+    }
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&(b"test.example.com".to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), Some(&(b"example.com".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"xample.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"ww.example.com"[..]), None);
+    assert_eq!(root.len(), 3);
+  }
+
+  #[test]
+  fn remove() {
+    let mut root: Art<u8> = Art::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 3), InsertResult::Ok);
+
+    assert_eq!(root.remove(&b"example.com"[..]), Some((Vec::from(&b"example.com"[..]), 3)));
+    assert_eq!(root.remove(&b"example.com"[..]), None);
+    assert_eq!(root.remove(&b"www.example.co"[..]), None);
+    assert_eq!(root.remove(&b"test.example.com"[..]), Some((Vec::from(&b"test.example.com"[..]), 2)));
+
+    // the inner nodes collapsed back into a leaf
+    assert_eq!(kind(&root), "Leaf");
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+
+    assert_eq!(root.remove(&b"WWW.example.com"[..]), Some((Vec::from(&b"www.example.com"[..]), 1)));
+    assert!(root.is_empty());
+    assert_eq!(root.len(), 0);
+  }
+
+  #[test]
+  fn node_layouts() {
+    let mut root: Art<u8> = Art::root();
+    // the keys only differ on their first byte, read right after `moc.elpmaxe.`.
+    // Lookups lowercase the host, so uppercase letters are skipped
+    let keys: Vec<Key> = (b'!'..b'~')
+      .filter(|&c| c != b'.' && !c.is_ascii_uppercase())
+      .take(64)
+      .map(|c| format!("{}.example.com", c as char).into_bytes())
+      .collect();
+
+    for (i, key) in keys.iter().enumerate() {
+      assert_eq!(root.insert(key.clone(), i as u8), InsertResult::Ok);
+      let expected = match i + 1 {
+        1       => "Leaf",
+        2..=4   => "Node4",
+        5..=16  => "Node16",
+        17..=48 => "Node48",
+        _       => "Node256",
+      };
+      assert_eq!(kind(&root), expected, "after {} insertions", i + 1);
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+      assert_eq!(root.domain_lookup(key).map(|kv| kv.1), Some(i as u8));
+    }
+
+    // nodes shrink later than they grew, to avoid switching back and forth
+    for (i, key) in keys.iter().enumerate().rev() {
+      assert_eq!(root.remove(key), Some((key.clone(), i as u8)));
+      let expected = match i {
+        1       => "Leaf",
+        2..=3   => "Node4",
+        4..=12  => "Node16",
+        13..=37 => "Node48",
+        _       => "Node256",
+      };
+      if i > 0 {
+        assert_eq!(kind(&root), expected, "after removing down to {} keys", i);
+        assert_eq!(root.domain_lookup(&keys[i - 1]).map(|kv| kv.1), Some(i as u8 - 1));
+      }
+    }
+    assert!(root.is_empty());
+  }
+
+  #[test]
+  fn domains() {
+    let mut root: Art<u8> = Art::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 3), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&(b"alldomains.org".to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
+    println!("after remove");
+    root.print();
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+  }
+}
@@ -0,0 +1,617 @@
+//! double-array trie
+//!
+//! the trie is stored in two arrays: the node in cell `s` has a child for the
+//! code `c` in cell `t = base[s] + c` if `check[t] == s`. Following a byte is
+//! two array accesses, and the arrays stay dense, so this is compact like the
+//! state machines of exp 4 and 6, but it changes in place:
+//! - a new child whose cell is used by another node moves every child of its
+//!   parent to a new base where they all fit (relocation)
+//! - removing a key frees its cells, and the cells of the nodes it leaves
+//!   without children. Later insertions and relocations reuse them
+//!
+//! keys are reversed domain names, read from their end. The bytes allowed in
+//! a key map to 40 codes (ASCII case is folded there), and code 0 marks the
+//! end of a key: `base` holds the index of the entry in that cell.
+//!
+//! `build` lays out a whole set of keys, like the ones `gen_bench_domains`
+//! generates, placing each node once all of its children are known, so it
+//! never relocates
+//!
+//! wildcards are matched while walking the host: at each label boundary, the
+//! walk tries the `.**` continuation, and before the first label, the `*` one
+
+use std::str;
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, specificity, is_under, validate_key};
+
+/// code of the end of a key
+const END:  u32 = 0;
+/// number of codes, END included
+const CODES: u32 = 41;
+/// `check` value of a free cell
+const FREE: u32 = u32::MAX;
+const ROOT: u32 = 0;
+
+/// byte of each code, for `print`
+const ALPHABET: &[u8] = b"$.*-_0123456789abcdefghijklmnopqrstuvwxyz";
+
+fn code(c: u8) -> Option<u32> {
+  match c {
+    b'.'        => Some(1),
+    b'*'        => Some(2),
+    b'-'        => Some(3),
+    b'_'        => Some(4),
+    b'0'..=b'9' => Some((c - b'0') as u32 + 5),
+    b'a'..=b'z' => Some((c - b'a') as u32 + 15),
+    b'A'..=b'Z' => Some((c - b'A') as u32 + 15),
+    _           => None,
+  }
+}
+
+pub struct DoubleArray<V> {
+  base:         Vec<u32>,
+  check:        Vec<u32>,
+  /// free cells below `check.len()`, to find new bases
+  free:         BTreeSet<u32>,
+  entries:      Vec<Option<KeyValue<Key,V>>>,
+  free_entries: Vec<u32>,
+  /// number of exact keys, single label wildcards and deep wildcards, to
+  /// skip the wildcard probes when there are none
+  counts:       [usize; 3],
+}
+
+impl<V: Debug> DoubleArray<V> {
+  pub fn root() -> DoubleArray<V> {
+    DoubleArray {
+      // cell 0 is the root. Bases are at least 1, so it is nobody's child
+      base:         vec![1],
+      check:        vec![ROOT],
+      free:         BTreeSet::new(),
+      entries:      Vec::new(),
+      free_entries: Vec::new(),
+      counts:       [0; 3],
+    }
+  }
+
+  /// lays out the keys at once. Keys are lowercased, and the ones that cannot
+  /// be stored are returned with the reason: `InsertResult::Existing` for
+  /// duplicates, `InsertResult::Failed` with the error otherwise
+  pub fn build(entries: Vec<(Key, V)>) -> (DoubleArray<V>, Vec<(Key, InsertResult)>) {
+    let mut root = DoubleArray::root();
+    let mut skipped = Vec::new();
+
+    // keys are encoded from their end, like `insert` walks them
+    let mut sorted: Vec<(Vec<u32>, Key, V)> = Vec::with_capacity(entries.len());
+    for (mut key, value) in entries {
+      key.make_ascii_lowercase();
+      if let Err(e) = validate_key(&key) {
+        skipped.push((key, InsertResult::Failed(e)));
+        continue;
+      }
+      match key.iter().rev().map(|&c| code(c)).collect::<Option<Vec<u32>>>() {
+        Some(codes) => sorted.push((codes, key, value)),
+        None        => skipped.push((key, InsertResult::Failed(InsertError::InvalidCharacter))),
+      }
+    }
+    // stable, so the first of duplicate keys is kept
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut encoded: Vec<(Vec<u32>, u32)> = Vec::with_capacity(sorted.len());
+    for (codes, key, value) in sorted {
+      if encoded.last().map(|last| last.0 == codes).unwrap_or(false) {
+        skipped.push((key, InsertResult::Existing));
+        continue;
+      }
+
+      root.counts[specificity(&key)] += 1;
+      encoded.push((codes, root.entries.len() as u32));
+      root.entries.push(Some((key, value)));
+    }
+
+    if !encoded.is_empty() {
+      root.place(ROOT, &encoded, 0);
+    }
+    (root, skipped)
+  }
+
+  /// gives cells to the children of `s`, then to their own children. The keys
+  /// are encoded, sorted, and share their first `depth` codes
+  fn place(&mut self, s: u32, keys: &[(Vec<u32>, u32)], depth: usize) {
+    // (code, start, end), the END code first since shorter keys sort first
+    let mut groups: Vec<(u32, usize, usize)> = Vec::new();
+    for (i, (codes, _)) in keys.iter().enumerate() {
+      let c = codes.get(depth).cloned().unwrap_or(END);
+      match groups.last_mut() {
+        Some(ref mut group) if group.0 == c => {
+          group.2 = i + 1;
+          continue;
+        },
+        _ => {},
+      }
+      groups.push((c, i, i + 1));
+    }
+
+    let codes: Vec<u32> = groups.iter().map(|g| g.0).collect();
+    let base = self.find_base(&codes);
+    self.base[s as usize] = base;
+    for &c in codes.iter() {
+      self.take(base + c, s);
+    }
+
+    for (c, start, end) in groups {
+      let t = base + c;
+      if c == END {
+        self.base[t as usize] = keys[start].1;
+      } else {
+        self.place(t, &keys[start..end], depth + 1);
+      }
+    }
+  }
+
+  fn child(&self, s: u32, c: u32) -> Option<u32> {
+    let t = self.base[s as usize] + c;
+    if (t as usize) < self.check.len() && self.check[t as usize] == s {
+      Some(t)
+    } else {
+      None
+    }
+  }
+
+  /// codes of the children of `s`, which must not be the end of a key
+  fn children(&self, s: u32) -> Vec<u32> {
+    (0..CODES).filter(|&c| self.child(s, c).is_some()).collect()
+  }
+
+  fn is_free(&self, t: u32) -> bool {
+    t as usize >= self.check.len() || self.check[t as usize] == FREE
+  }
+
+  /// marks the cell as a child of `parent`, growing the arrays if needed
+  fn take(&mut self, t: u32, parent: u32) {
+    while self.check.len() <= t as usize {
+      self.free.insert(self.check.len() as u32);
+      self.base.push(0);
+      self.check.push(FREE);
+    }
+
+    self.free.remove(&t);
+    self.base[t as usize] = 0;
+    self.check[t as usize] = parent;
+  }
+
+  fn release(&mut self, t: u32) {
+    self.base[t as usize] = 0;
+    self.check[t as usize] = FREE;
+    self.free.insert(t);
+  }
+
+  /// the first base where every code lands on a free cell, starting from the
+  /// free cells inside the arrays
+  fn find_base(&self, codes: &[u32]) -> u32 {
+    let first = *codes.iter().min().unwrap();
+    for &cell in self.free.range(first + 1..) {
+      let base = cell - first;
+      if codes.iter().all(|&c| self.is_free(base + c)) {
+        return base;
+      }
+    }
+
+    // after the end of the arrays
+    ::std::cmp::max(1, (self.check.len() as u32).saturating_sub(first))
+  }
+
+  /// moves the children of `s` to a new base where they and the new code fit
+  fn relocate(&mut self, s: u32, new_code: u32) {
+    let codes = self.children(s);
+    let mut all = codes.clone();
+    all.push(new_code);
+    let old_base = self.base[s as usize];
+    let new_base = self.find_base(&all);
+
+    for &c in codes.iter() {
+      let (old, new) = (old_base + c, new_base + c);
+      self.take(new, s);
+      self.base[new as usize] = self.base[old as usize];
+      if c != END {
+        // the grandchildren follow their parent
+        for g in self.children(old) {
+          let grandchild = self.base[old as usize] + g;
+          self.check[grandchild as usize] = new;
+        }
+      }
+      self.release(old);
+    }
+    self.base[s as usize] = new_base;
+  }
+
+  fn add_child(&mut self, s: u32, c: u32) -> u32 {
+    if !self.is_free(self.base[s as usize] + c) {
+      self.relocate(s, c);
+    }
+
+    let t = self.base[s as usize] + c;
+    self.take(t, s);
+    t
+  }
+
+  /// frees the cells at the end of the arrays
+  fn truncate(&mut self) {
+    while self.check.len() > 1 && self.check[self.check.len() - 1] == FREE {
+      let last = self.check.len() - 1;
+      self.free.remove(&(last as u32));
+      self.base.pop();
+      self.check.pop();
+    }
+  }
+
+  /// inserts a lowercased key
+  pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
+    if key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
+    // checked before creating any node
+    if key.iter().any(|&c| code(c).is_none()) {
+      return InsertResult::Failed(InsertError::InvalidCharacter);
+    }
+
+    let mut s = ROOT;
+    for &c in key.iter().rev() {
+      let c = code(c).unwrap();
+      s = match self.child(s, c) {
+        Some(t) => t,
+        None    => self.add_child(s, c),
+      };
+    }
+
+    if self.child(s, END).is_some() {
+      return InsertResult::Existing;
+    }
+
+    let slot = match self.free_entries.pop() {
+      Some(slot) => slot,
+      None => {
+        self.entries.push(None);
+        self.entries.len() as u32 - 1
+      },
+    };
+    self.counts[specificity(&key)] += 1;
+    self.entries[slot as usize] = Some((key, value));
+
+    let t = self.add_child(s, END);
+    self.base[t as usize] = slot;
+    InsertResult::Ok
+  }
+
+  /// removes the key, and returns the entry
+  pub fn remove(&mut self, key: &[u8]) -> Option<KeyValue<Key,V>> {
+    let mut path = vec![ROOT];
+    for &c in key.iter().rev() {
+      let t = self.child(*path.last().unwrap(), code(c)?)?;
+      path.push(t);
+    }
+
+    let end = self.child(*path.last().unwrap(), END)?;
+    let slot = self.base[end as usize];
+    self.release(end);
+
+    // nodes left without children do not lead to any key
+    while path.len() > 1 {
+      let s = path.pop().unwrap();
+      if !self.children(s).is_empty() {
+        break;
+      }
+      self.release(s);
+    }
+    self.truncate();
+
+    let removed = self.entries[slot as usize].take();
+    self.free_entries.push(slot);
+    if let Some(ref kv) = removed {
+      self.counts[specificity(&kv.0)] -= 1;
+    }
+    removed
+  }
+
+  /// follows the bytes from `s`, then the end of the key, and returns the entry
+  fn probe(&self, s: u32, tail: &[u8]) -> Option<u32> {
+    let mut s = s;
+    for &c in tail.iter() {
+      s = self.child(s, code(c).unwrap())?;
+    }
+    self.child(s, END).map(|t| self.base[t as usize])
+  }
+
+  /// walks the host from its end, and returns the entries of the exact key and
+  /// of the single label wildcard. Deep wildcards are given to `deep` on the
+  /// way, from the least specific to the most specific
+  fn find<F>(&self, host: &[u8], mut deep: F) -> (Option<u32>, Option<u32>) where F: FnMut(u32) {
+    if host.is_empty() {
+      return (None, None);
+    }
+
+    // the single wildcard follows the host without its first label, or is `*`
+    let single_depth = host.iter().position(|&c| c == b'.').map(|dot| host.len() - dot).unwrap_or(0);
+    let mut single = None;
+    let mut s = ROOT;
+    let mut depth = 0;
+    loop {
+      if self.counts[2] > 0 {
+        // `**` matches every host, `**.example.com` once `example.com` was read
+        let tail: &[u8] = if depth == 0 { b"**" } else { b".**" };
+        if depth == 0 || depth == host.len() || host[host.len() - 1 - depth] == b'.' {
+          if let Some(slot) = self.probe(s, tail) {
+            deep(slot);
+          }
+        }
+      }
+
+      if self.counts[1] > 0 && depth == single_depth {
+        single = self.probe(s, b"*");
+      }
+
+      if depth == host.len() {
+        return (self.probe(s, b""), single);
+      }
+
+      s = match code(host[host.len() - 1 - depth]).and_then(|c| self.child(s, c)) {
+        Some(t) => t,
+        None    => return (None, single),
+      };
+      depth += 1;
+    }
+  }
+
+  fn entry(&self, slot: u32) -> Option<&KeyValue<Key,V>> {
+    self.entries[slot as usize].as_ref()
+  }
+
+  /// node reached by the reversed bytes
+  fn walk(&self, key: &[u8]) -> Option<u32> {
+    let mut s = ROOT;
+    for &c in key.iter().rev() {
+      s = self.child(s, code(c)?)?;
+    }
+    Some(s)
+  }
+
+  fn collect<'a>(&'a self, s: u32, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    for c in self.children(s) {
+      let t = self.base[s as usize] + c;
+      if c == END {
+        entries.extend(self.entry(self.base[t as usize]));
+      } else {
+        self.collect(t, entries);
+      }
+    }
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<'_, V> {
+    Iter::new(self.entries.iter().filter_map(|kv| kv.as_ref()).collect())
+  }
+
+  /// entries for the domain and its subdomains, found under the node of the
+  /// reversed domain
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<'_, V> {
+    let mut entries = Vec::new();
+    if let Some(s) = self.walk(domain) {
+      self.collect(s, &mut entries);
+    }
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.counts.iter().sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// bytes used by the arrays and the entries
+  pub fn size(&self) -> usize {
+    ::std::mem::size_of::<DoubleArray<V>>()
+      + (self.base.len() + self.check.len() + self.free.len() + self.free_entries.len()) * ::std::mem::size_of::<u32>()
+      + self.entries.len() * ::std::mem::size_of::<Option<KeyValue<Key, V>>>()
+      + self.entries.iter().filter_map(|kv| kv.as_ref()).fold(0, |acc, kv| acc + kv.0.len())
+  }
+
+  pub fn print(&self) {
+    println!("{} cells, {} free", self.check.len(), self.free.len());
+    self.print_recursive(ROOT, 0);
+  }
+
+  fn print_recursive(&self, s: u32, indent: usize) {
+    let prefix = " ".repeat(2 * indent);
+    for c in self.children(s) {
+      let t = self.base[s as usize] + c;
+      if c == END {
+        if let Some((key, value)) = self.entry(self.base[t as usize]) {
+          println!("{}[{}]: ({},{:?})", prefix, t, str::from_utf8(key).unwrap(), value);
+        }
+      } else {
+        println!("{}[{}] {}", prefix, t, ALPHABET[c as usize] as char);
+        self.print_recursive(t, indent + 1);
+      }
+    }
+  }
+}
+
+impl<V: Debug> DomainLookup<V> for DoubleArray<V> {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
+    self.insert(key, value)
+  }
+
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    self.remove(key).into()
+  }
+
+  fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let mut deepest = None;
+    let (exact, single) = self.find(key, |slot| deepest = Some(slot));
+    exact.or(single).or(deepest).and_then(|slot| self.entry(slot))
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let s = self.walk(key)?;
+    let t = self.child(s, END)?;
+    let slot = self.base[t as usize];
+    self.entries[slot as usize].as_mut().map(|kv| &mut kv.1)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut deep = Vec::new();
+    let (exact, single) = self.find(key, |slot| deep.push(slot));
+    exact.into_iter().chain(single).chain(deep.into_iter().rev())
+      .filter_map(|slot| self.entry(slot))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn insert() {
+    let mut root: DoubleArray<u8> = DoubleArray::root();
+
+    assert_eq!(root.insert(Vec::from(&b"abcd"[..]), 1), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.insert(Vec::from(&b"abce"[..]), 2), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.insert(Vec::from(&b"abgh"[..]), 3), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.insert(Vec::from(&b"abgh"[..]), 4), InsertResult::Existing);
+    assert_eq!(root.insert(Vec::from(&b"ab/h"[..]), 4), InsertResult::Failed(InsertError::InvalidCharacter));
+
+    assert_eq!(root.domain_lookup(&b"abce"[..]), Some(&(b"abce".to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"abgh"[..]), Some(&(b"abgh".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"bcd"[..]), None);
+    assert_eq!(root.len(), 3);
+  }
+
+  #[test]
+  fn relocation() {
+    let mut root: DoubleArray<u8> = DoubleArray::root();
+
+    // `b` and `a` fill the cells next to the root's base, so the second
+    // child of `b` does not fit and `b`'s children move
+    assert_eq!(root.insert(Vec::from(&b"xb"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"a"[..]), 2), InsertResult::Ok);
+    let b = root.child(ROOT, code(b'b').unwrap()).unwrap();
+    let before = root.base[b as usize];
+    for (i, c) in b"0123456789".iter().enumerate() {
+      assert_eq!(root.insert(vec![*c, b'b'], 10 + i as u8), InsertResult::Ok);
+    }
+    root.print();
+    assert!(root.base[b as usize] != before);
+
+    assert_eq!(root.domain_lookup(&b"xb"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"a"[..]).map(|kv| kv.1), Some(2));
+    for (i, c) in b"0123456789".iter().enumerate() {
+      assert_eq!(root.domain_lookup(&[*c, b'b'][..]).map(|kv| kv.1), Some(10 + i as u8));
+    }
+    // every cell is used once
+    let used = root.check.iter().filter(|&&c| c != FREE).count();
+    assert_eq!(used + root.free.len(), root.check.len());
+  }
+
+  #[test]
+  fn remove() {
+    let mut root: DoubleArray<u8> = DoubleArray::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    let cells = root.check.len();
+    assert_eq!(root.insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 3), InsertResult::Ok);
+
+    assert_eq!(root.remove(&b"example.com"[..]), Some((Vec::from(&b"example.com"[..]), 3)));
+    assert_eq!(root.remove(&b"example.com"[..]), None);
+    assert_eq!(root.remove(&b"www.example.co"[..]), None);
+    assert_eq!(root.remove(&b"test.example.com"[..]), Some((Vec::from(&b"test.example.com"[..]), 2)));
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+    assert!(root.check.len() <= cells);
+
+    // the freed entry is reused
+    assert_eq!(root.insert(Vec::from(&b"test.example.com"[..]), 4), InsertResult::Ok);
+    assert_eq!(root.entries.len(), 3);
+
+    assert_eq!(root.remove(&b"WWW.example.com"[..]), Some((Vec::from(&b"www.example.com"[..]), 1)));
+    assert_eq!(root.remove(&b"test.example.com"[..]), Some((Vec::from(&b"test.example.com"[..]), 4)));
+    assert!(root.is_empty());
+    // only the root is left
+    assert_eq!(root.check.len(), 1);
+    assert!(root.free.is_empty());
+  }
+
+  #[test]
+  fn build() {
+    let entries = vec![
+      (Vec::from(&b"www.example.com"[..]), 1),
+      (Vec::from(&b"*.example.com"[..]), 2),
+      (Vec::from(&b"Example.com"[..]), 3),
+      (Vec::from(&b"example.com"[..]), 4),
+      (Vec::from(&b"example..com"[..]), 5),
+      (Vec::from(&b"**.example.org"[..]), 6),
+    ];
+    let (mut root, skipped) = DoubleArray::build(entries);
+    root.print();
+
+    assert_eq!(skipped, vec![
+      (Vec::from(&b"example..com"[..]), InsertResult::Failed(InsertError::InvalidPattern)),
+      (Vec::from(&b"example.com"[..]), InsertResult::Existing),
+    ]);
+    assert_eq!(root.len(), 4);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]).map(|kv| kv.1), Some(2));
+    assert_eq!(root.domain_lookup(&b"example.com"[..]).map(|kv| kv.1), Some(3));
+    assert_eq!(root.domain_lookup(&b"a.b.example.org"[..]).map(|kv| kv.1), Some(6));
+
+    // the built trie can still change
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 7), InsertResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"*.example.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]).map(|kv| kv.1), Some(7));
+    assert_eq!(root.domain_lookup(&b"other.example.com"[..]), None);
+  }
+
+  #[test]
+  fn domains() {
+    let mut root: DoubleArray<u8> = DoubleArray::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 3), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&(b"alldomains.org".to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
+    println!("after remove");
+    root.print();
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+  }
+}
@@ -0,0 +1,609 @@
+//! burst trie, with array hash buckets
+//!
+//! the tree of hashmaps of exp 9 spends a whole `HashMap` on every label. Here,
+//! the keys sharing a path are stored in a bucket: a small array hash whose
+//! slots are contiguous byte arrays holding the rest of each key next to the
+//! index of its entry, so a lookup hashes the rest of the host once, then
+//! scans a single array. A bucket holding more than `BURST` keys bursts: it
+//! becomes a trie node, and its keys go to one new bucket per next byte.
+//!
+//! keys are read from their end, like reversed domains, so the rest of a key
+//! below a node is its beginning: `www.example` for `www.example.com` under the
+//! `moc.` path.
+//!
+//! wildcards are looked up like in the sozu trie: the host, then the host
+//! with its first label replaced by `*`, then `**.` followed by the host and by
+//! each of its parent domains
+
+use std::{iter,str};
+use std::fmt::Debug;
+
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, specificity, is_under, validate_key};
+
+/// number of slots in a bucket
+const SLOTS: usize = 16;
+/// a bucket bursts when it holds more keys than this
+const BURST: usize = 128;
+
+/// a key as the tree reads it: `head` followed by `host`. Wildcards are looked
+/// up with `*` or `**.` as head, without building the key
+struct Probe<'a> {
+  head: &'static [u8],
+  host: &'a [u8],
+}
+
+impl<'a> Probe<'a> {
+  fn new(head: &'static [u8], host: &'a [u8]) -> Probe<'a> {
+    Probe { head, host }
+  }
+
+  fn len(&self) -> usize {
+    self.head.len() + self.host.len()
+  }
+
+  /// byte at this depth from the end, lowercased
+  fn at(&self, depth: usize) -> u8 {
+    if depth < self.host.len() {
+      self.host[self.host.len() - 1 - depth].to_ascii_lowercase()
+    } else {
+      self.head[self.head.len() - 1 - (depth - self.host.len())]
+    }
+  }
+
+  /// the bytes left to read below this depth, as two slices
+  fn rest(&self, depth: usize) -> (&'static [u8], &'a [u8]) {
+    let len = self.len() - depth;
+    if len <= self.head.len() {
+      (&self.head[..len], &self.host[..0])
+    } else {
+      (self.head, &self.host[..len - self.head.len()])
+    }
+  }
+}
+
+/// FNV-1a of the lowercased bytes
+fn hash_slot(parts: (&[u8], &[u8])) -> usize {
+  let mut hash: u32 = 0x811c_9dc5;
+  for &c in parts.0.iter().chain(parts.1.iter()) {
+    hash ^= c.to_ascii_lowercase() as u32;
+    hash = hash.wrapping_mul(0x0100_0193);
+  }
+  hash as usize % SLOTS
+}
+
+/// array hash: each slot stores its keys one after the other, as the length
+/// on two bytes, the bytes, and the index of the entry on four bytes
+struct Bucket {
+  slots: Vec<Vec<u8>>,
+  len:   usize,
+}
+
+impl Bucket {
+  fn new() -> Bucket {
+    Bucket {
+      slots: Vec::new(),
+      len:   0,
+    }
+  }
+
+  /// position of the key in its slot, with the index of its entry
+  fn position(&self, parts: (&[u8], &[u8])) -> Option<(usize, usize, u32)> {
+    if self.len == 0 {
+      return None;
+    }
+
+    let slot = hash_slot(parts);
+    let bytes = &self.slots[slot];
+    let len = parts.0.len() + parts.1.len();
+    let mut pos = 0;
+    while pos < bytes.len() {
+      let stored = (bytes[pos] as usize) | (bytes[pos + 1] as usize) << 8;
+      let start = pos + 2;
+      let end = start + stored;
+      if stored == len
+        && bytes[start..start + parts.0.len()].eq_ignore_ascii_case(parts.0)
+        && bytes[start + parts.0.len()..end].eq_ignore_ascii_case(parts.1) {
+        let entry = bytes[end..end + 4].iter().rev().fold(0, |acc, &b| acc << 8 | b as u32);
+        return Some((slot, pos, entry));
+      }
+      pos = end + 4;
+    }
+    None
+  }
+
+  fn get(&self, probe: &Probe, depth: usize) -> Option<u32> {
+    self.position(probe.rest(depth)).map(|(_, _, entry)| entry)
+  }
+
+  /// adds a lowercased key, false if it is already there
+  fn insert(&mut self, rest: &[u8], entry: u32) -> bool {
+    if self.position((rest, &b""[..])).is_some() {
+      return false;
+    }
+
+    if self.slots.is_empty() {
+      self.slots = (0..SLOTS).map(|_| Vec::new()).collect();
+    }
+    let bytes = &mut self.slots[hash_slot((rest, &b""[..]))];
+    bytes.push(rest.len() as u8);
+    bytes.push((rest.len() >> 8) as u8);
+    bytes.extend_from_slice(rest);
+    bytes.extend((0..4).map(|i| (entry >> (8 * i)) as u8));
+    self.len += 1;
+    true
+  }
+
+  fn remove(&mut self, probe: &Probe, depth: usize) -> Option<u32> {
+    let (slot, pos, entry) = self.position(probe.rest(depth))?;
+    let end = pos + 2 + ((self.slots[slot][pos] as usize) | (self.slots[slot][pos + 1] as usize) << 8) + 4;
+    self.slots[slot].drain(pos..end);
+    self.slots[slot].shrink_to_fit();
+    self.len -= 1;
+    if self.len == 0 {
+      self.slots = Vec::new();
+    }
+    Some(entry)
+  }
+
+  /// every key, with the index of its entry
+  fn items(&self) -> Vec<(&[u8], u32)> {
+    let mut items = Vec::with_capacity(self.len);
+    for bytes in self.slots.iter() {
+      let mut pos = 0;
+      while pos < bytes.len() {
+        let end = pos + 2 + ((bytes[pos] as usize) | (bytes[pos + 1] as usize) << 8);
+        let entry = bytes[end..end + 4].iter().rev().fold(0, |acc, &b| acc << 8 | b as u32);
+        items.push((&bytes[pos + 2..end], entry));
+        pos = end + 4;
+      }
+    }
+    items
+  }
+
+  /// the trie node replacing this bucket: the key ending here becomes the
+  /// entry of the node, the others go to the bucket of their last byte
+  fn burst(&self) -> Inner {
+    let mut inner = Inner::new();
+    for (rest, entry) in self.items() {
+      match rest.split_last() {
+        None => inner.entry = Some(entry),
+        Some((&c, rest)) => {
+          let index = match inner.keys.binary_search(&c) {
+            Ok(index) => index,
+            Err(index) => {
+              inner.keys.insert(index, c);
+              inner.children.insert(index, Node::Bucket(Bucket::new()));
+              index
+            },
+          };
+          if let Node::Bucket(ref mut bucket) = inner.children[index] {
+            bucket.insert(rest, entry);
+          }
+        },
+      }
+    }
+
+    // every key could share the same byte
+    for child in inner.children.iter_mut() {
+      child.burst_if_full();
+    }
+    inner
+  }
+}
+
+struct Inner {
+  /// the key ending at this node
+  entry:    Option<u32>,
+  /// sorted bytes of the children
+  keys:     Vec<u8>,
+  children: Vec<Node>,
+}
+
+impl Inner {
+  fn new() -> Inner {
+    Inner {
+      entry:    None,
+      keys:     Vec::new(),
+      children: Vec::new(),
+    }
+  }
+}
+
+enum Node {
+  Bucket(Bucket),
+  Trie(Box<Inner>),
+}
+
+impl Node {
+  fn burst_if_full(&mut self) {
+    let inner = match *self {
+      Node::Bucket(ref bucket) if bucket.len > BURST => bucket.burst(),
+      _ => return,
+    };
+    *self = Node::Trie(Box::new(inner));
+  }
+
+  fn is_empty(&self) -> bool {
+    match *self {
+      Node::Bucket(ref bucket) => bucket.len == 0,
+      Node::Trie(ref inner) => inner.entry.is_none() && inner.children.is_empty(),
+    }
+  }
+
+  fn find(&self, probe: &Probe) -> Option<u32> {
+    let mut node = self;
+    let mut depth = 0;
+    loop {
+      let inner = match *node {
+        Node::Bucket(ref bucket) => return bucket.get(probe, depth),
+        Node::Trie(ref inner) => inner,
+      };
+
+      if depth == probe.len() {
+        return inner.entry;
+      }
+
+      let index = inner.keys.binary_search(&probe.at(depth)).ok()?;
+      node = &inner.children[index];
+      depth += 1;
+    }
+  }
+
+  /// `key` is lowercased
+  fn insert(&mut self, key: &[u8], depth: usize, entry: u32) -> bool {
+    let inserted = match *self {
+      Node::Bucket(ref mut bucket) => bucket.insert(&key[..key.len() - depth], entry),
+      Node::Trie(ref mut inner) => {
+        if depth == key.len() {
+          if inner.entry.is_some() {
+            return false;
+          }
+          inner.entry = Some(entry);
+          return true;
+        }
+
+        let c = key[key.len() - 1 - depth];
+        let index = match inner.keys.binary_search(&c) {
+          Ok(index) => index,
+          Err(index) => {
+            inner.keys.insert(index, c);
+            inner.children.insert(index, Node::Bucket(Bucket::new()));
+            index
+          },
+        };
+        return inner.children[index].insert(key, depth + 1, entry);
+      },
+    };
+
+    self.burst_if_full();
+    inserted
+  }
+
+  /// removes the key, and the children it leaves empty
+  fn remove(&mut self, probe: &Probe, depth: usize) -> Option<u32> {
+    match *self {
+      Node::Bucket(ref mut bucket) => bucket.remove(probe, depth),
+      Node::Trie(ref mut inner) => {
+        if depth == probe.len() {
+          return inner.entry.take();
+        }
+
+        let index = inner.keys.binary_search(&probe.at(depth)).ok()?;
+        let removed = inner.children[index].remove(probe, depth + 1);
+        if inner.children[index].is_empty() {
+          inner.keys.remove(index);
+          inner.children.remove(index);
+        }
+        removed
+      },
+    }
+  }
+
+  /// entries of the node, and of the nodes below it
+  fn collect(&self, entries: &mut Vec<u32>) {
+    match *self {
+      Node::Bucket(ref bucket) => entries.extend(bucket.items().into_iter().map(|(_, entry)| entry)),
+      Node::Trie(ref inner) => {
+        entries.extend(inner.entry);
+        for child in inner.children.iter() {
+          child.collect(entries);
+        }
+      },
+    }
+  }
+
+  /// the entries of the domain's subtree, or of the bucket where the domain's
+  /// path ends
+  fn collect_under(&self, domain: &Probe, depth: usize, entries: &mut Vec<u32>) {
+    match *self {
+      Node::Trie(ref inner) if depth < domain.len() => {
+        if let Ok(index) = inner.keys.binary_search(&domain.at(depth)) {
+          inner.children[index].collect_under(domain, depth + 1, entries);
+        }
+      },
+      _ => self.collect(entries),
+    }
+  }
+
+  fn print(&self, indent: usize) {
+    let prefix = " ".repeat(2 * indent);
+    match *self {
+      Node::Bucket(ref bucket) => {
+        for (rest, entry) in bucket.items() {
+          println!("{}\"{}\" -> {}", prefix, str::from_utf8(rest).unwrap(), entry);
+        }
+      },
+      Node::Trie(ref inner) => {
+        if let Some(entry) = inner.entry {
+          println!("{}-> {}", prefix, entry);
+        }
+        for (c, child) in inner.keys.iter().zip(inner.children.iter()) {
+          println!("{}{}:", prefix, *c as char);
+          child.print(indent + 1);
+        }
+      },
+    }
+  }
+}
+
+pub struct BurstTrie<V> {
+  root:         Node,
+  entries:      Vec<Option<KeyValue<Key,V>>>,
+  free_entries: Vec<u32>,
+  /// number of exact keys, single label wildcards and deep wildcards, to
+  /// skip the wildcard lookups when there are none
+  counts:       [usize; 3],
+}
+
+impl<V: Debug> BurstTrie<V> {
+  pub fn root() -> BurstTrie<V> {
+    BurstTrie {
+      root:         Node::Bucket(Bucket::new()),
+      entries:      Vec::new(),
+      free_entries: Vec::new(),
+      counts:       [0; 3],
+    }
+  }
+
+  /// inserts a lowercased key
+  pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
+    if key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
+    // the length of a key is stored on two bytes
+    if key.len() > 0xffff {
+      return InsertResult::Failed(InsertError::Conflict);
+    }
+
+    let entry = match self.free_entries.last() {
+      Some(&entry) => entry,
+      None         => self.entries.len() as u32,
+    };
+    if !self.root.insert(&key, 0, entry) {
+      return InsertResult::Existing;
+    }
+
+    if self.free_entries.pop().is_none() {
+      self.entries.push(None);
+    }
+    self.counts[specificity(&key)] += 1;
+    self.entries[entry as usize] = Some((key, value));
+    InsertResult::Ok
+  }
+
+  /// removes the key, and returns the entry
+  pub fn remove(&mut self, key: &[u8]) -> Option<KeyValue<Key,V>> {
+    let entry = self.root.remove(&Probe::new(b"", key), 0)?;
+    if self.root.is_empty() {
+      self.root = Node::Bucket(Bucket::new());
+    }
+
+    self.free_entries.push(entry);
+    let removed = self.entries[entry as usize].take();
+    if let Some(ref kv) = removed {
+      self.counts[specificity(&kv.0)] -= 1;
+    }
+    removed
+  }
+
+  fn get(&self, probe: &Probe) -> Option<&KeyValue<Key,V>> {
+    self.root.find(probe).and_then(|entry| self.entries[entry as usize].as_ref())
+  }
+
+  /// calls `f` with the keys matching the host, from the most specific to
+  /// the least specific, until it returns false
+  fn for_each_match<'a, F>(&'a self, host: &[u8], mut f: F) where F: FnMut(&'a KeyValue<Key,V>) -> bool {
+    if host.is_empty() {
+      return;
+    }
+
+    if let Some(kv) = self.get(&Probe::new(b"", host)) {
+      if !f(kv) {
+        return;
+      }
+    }
+
+    if self.counts[1] > 0 {
+      // a host without dots matches the `*` key
+      let rest = match host.iter().position(|&c| c == b'.') {
+        Some(dot) => &host[dot..],
+        None      => &host[..0],
+      };
+      if let Some(kv) = self.get(&Probe::new(b"*", rest)) {
+        if !f(kv) {
+          return;
+        }
+      }
+    }
+
+    if self.counts[2] > 0 {
+      let parents = host.iter().enumerate().filter(|&(_, &c)| c == b'.').map(|(i, _)| &host[i + 1..]);
+      for domain in iter::once(host).chain(parents) {
+        if let Some(kv) = self.get(&Probe::new(b"**.", domain)) {
+          if !f(kv) {
+            return;
+          }
+        }
+      }
+
+      if let Some(kv) = self.get(&Probe::new(b"**", b"")) {
+        f(kv);
+      }
+    }
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<'_, V> {
+    Iter::new(self.entries.iter().filter_map(|kv| kv.as_ref()).collect())
+  }
+
+  /// entries for the domain and its subdomains
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<'_, V> {
+    let mut found = Vec::new();
+    self.root.collect_under(&Probe::new(b"", domain), 0, &mut found);
+    Iter::new(found.into_iter()
+      .filter_map(|entry| self.entries[entry as usize].as_ref())
+      .filter(|kv| is_under(&kv.0, domain))
+      .collect())
+  }
+
+  pub fn len(&self) -> usize {
+    self.counts.iter().sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  pub fn print(&self) {
+    self.root.print(0);
+  }
+}
+
+impl<V: Debug> DomainLookup<V> for BurstTrie<V> {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
+    self.insert(key, value)
+  }
+
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    self.remove(key).into()
+  }
+
+  fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let mut found = None;
+    self.for_each_match(key, |kv| {
+      found = Some(kv);
+      false
+    });
+    found
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let entry = self.root.find(&Probe::new(b"", key))?;
+    self.entries[entry as usize].as_mut().map(|kv| &mut kv.1)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut matches = Vec::new();
+    self.for_each_match(key, |kv| {
+      matches.push(kv);
+      true
+    });
+    matches
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn insert() {
+    let mut root: BurstTrie<u8> = BurstTrie::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 4), InsertResult::Existing);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"Test.Example.com"[..]), Some(&(b"test.example.com".to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), Some(&(b"example.com".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"xample.com"[..]), None);
+    assert_eq!(root.len(), 3);
+    // the entry of the failed insertion is not used
+    assert_eq!(root.entries.len(), 3);
+  }
+
+  #[test]
+  fn burst() {
+    let mut root: BurstTrie<u16> = BurstTrie::root();
+    let keys: Vec<Key> = (0..1000u16).map(|i| format!("host{}.example{}.com", i, i % 3).into_bytes()).collect();
+
+    for (i, key) in keys.iter().take(BURST).enumerate() {
+      assert_eq!(root.insert(key.clone(), i as u16), InsertResult::Ok);
+    }
+    match root.root {
+      Node::Bucket(ref bucket) => assert_eq!(bucket.len, BURST),
+      Node::Trie(_) => panic!("the bucket should not burst yet"),
+    }
+
+    for (i, key) in keys.iter().enumerate().skip(BURST) {
+      assert_eq!(root.insert(key.clone(), i as u16), InsertResult::Ok);
+    }
+    // every key shares `moc.`, so the buckets burst down to the domains
+    match root.root {
+      Node::Trie(ref inner) => assert_eq!(&inner.keys[..], &b"m"[..]),
+      Node::Bucket(_) => panic!("the bucket should have burst"),
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+      assert_eq!(root.domain_lookup(key).map(|kv| kv.1), Some(i as u16));
+    }
+    assert_eq!(root.iter_under(&b"example1.com"[..]).count(), 333);
+
+    for (i, key) in keys.iter().enumerate() {
+      assert_eq!(root.remove(key), Some((key.clone(), i as u16)));
+    }
+    assert!(root.is_empty());
+    assert!(root.root.is_empty());
+  }
+
+  #[test]
+  fn domains() {
+    let mut root: BurstTrie<u8> = BurstTrie::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 3), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&(b"alldomains.org".to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
+    println!("after remove");
+    root.print();
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+  }
+}
@@ -0,0 +1,440 @@
+//! trie of interned labels
+//!
+//! labels like `com` or `www` appear in thousands of keys. Here every label is
+//! stored once, in a symbol table shared by the whole trie that gives it a
+//! `u32` id, and the trie nodes are keyed by those ids, from the last label to
+//! the first one.
+//!
+//! a lookup finds the id of each host label, right to left, and follows it.
+//! A label missing from the table cannot be in any key, so the lookup stops
+//! there: unknown hosts are usually rejected after one hash of their TLD or
+//! domain, with only the wildcards met on the way left to check.
+//!
+//! `*` and `**` have reserved ids: `*.example.com` is the `*` child of the
+//! `example.com` node, and `**.example.com` its `**` child
+
+use std::str;
+use std::fmt::Debug;
+
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, specificity, is_under, validate_key};
+use hashbrown::HashMap;
+
+const WILDCARD:      u32 = 0;
+const DEEP_WILDCARD: u32 = 1;
+
+/// DNS labels are at most 63 bytes long
+const LABEL_BUFFER_SIZE: usize = 63;
+
+/// ids of the labels used by the keys, with the number of times each label is
+/// used, so the ids of removed labels can be given again
+struct Symbols {
+  ids:    HashMap<Key, u32>,
+  labels: Vec<(Key, usize)>,
+  free:   Vec<u32>,
+}
+
+impl Symbols {
+  fn new() -> Symbols {
+    let mut ids = HashMap::new();
+    ids.insert(Vec::from(&b"*"[..]), WILDCARD);
+    ids.insert(Vec::from(&b"**"[..]), DEEP_WILDCARD);
+
+    Symbols {
+      ids,
+      labels: vec![(Vec::from(&b"*"[..]), 0), (Vec::from(&b"**"[..]), 0)],
+      free:   Vec::new(),
+    }
+  }
+
+  /// id of the label, whatever its case
+  fn find(&self, label: &[u8]) -> Option<u32> {
+    if !label.iter().any(|c| c.is_ascii_uppercase()) {
+      return self.ids.get(label).cloned();
+    }
+
+    if label.len() > LABEL_BUFFER_SIZE {
+      return self.ids.get(&label.to_ascii_lowercase()[..]).cloned();
+    }
+    let mut buffer = [0; LABEL_BUFFER_SIZE];
+    let lowercase = &mut buffer[..label.len()];
+    lowercase.copy_from_slice(label);
+    lowercase.make_ascii_lowercase();
+    self.ids.get(&lowercase[..]).cloned()
+  }
+
+  /// id of a lowercased label, given to it if it has none
+  fn intern(&mut self, label: &[u8]) -> u32 {
+    let id = match self.ids.get(label) {
+      Some(&id) => id,
+      None => {
+        let id = match self.free.pop() {
+          Some(id) => {
+            self.labels[id as usize] = (label.to_vec(), 0);
+            id
+          },
+          None => {
+            self.labels.push((label.to_vec(), 0));
+            self.labels.len() as u32 - 1
+          },
+        };
+        self.ids.insert(label.to_vec(), id);
+        id
+      },
+    };
+
+    self.labels[id as usize].1 += 1;
+    id
+  }
+
+  /// one key does not use this label anymore
+  fn release(&mut self, id: u32) {
+    let &mut (ref label, ref mut count) = &mut self.labels[id as usize];
+    *count -= 1;
+    if *count == 0 && id != WILDCARD && id != DEEP_WILDCARD {
+      self.ids.remove(label);
+      self.free.push(id);
+    }
+  }
+
+  fn label(&self, id: u32) -> &[u8] {
+    &self.labels[id as usize].0
+  }
+
+  fn len(&self) -> usize {
+    self.ids.len()
+  }
+}
+
+#[derive(Debug)]
+struct Node<V> {
+  key_value: Option<KeyValue<Key,V>>,
+  children:  HashMap<u32, Node<V>>,
+}
+
+impl<V> Node<V> {
+  fn new() -> Node<V> {
+    Node {
+      key_value: None,
+      children:  HashMap::new(),
+    }
+  }
+
+  fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.is_empty()
+  }
+
+  /// `ids` go from the last label to the first one
+  fn find(&self, ids: &[u32]) -> Option<&Node<V>> {
+    let mut node = self;
+    for id in ids.iter() {
+      node = node.children.get(id)?;
+    }
+    Some(node)
+  }
+
+  fn remove(&mut self, ids: &[u32]) -> Option<KeyValue<Key,V>> {
+    match ids.split_first() {
+      None => self.key_value.take(),
+      Some((id, rest)) => {
+        let removed = self.children.get_mut(id)?.remove(rest);
+        if self.children.get(id).map(|child| child.is_empty()).unwrap_or(false) {
+          self.children.remove(id);
+        }
+        removed
+      },
+    }
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.values() {
+      child.collect(entries);
+    }
+  }
+}
+
+impl<V: Debug> Node<V> {
+  fn print(&self, symbols: &Symbols, indent: usize) {
+    let prefix = " ".repeat(2 * indent);
+    for (id, child) in self.children.iter() {
+      match child.key_value {
+        Some((ref key, ref value)) => println!("{}{} [{}]: ({},{:?})", prefix,
+          str::from_utf8(symbols.label(*id)).unwrap(), id, str::from_utf8(key).unwrap(), value),
+        None => println!("{}{} [{}]", prefix, str::from_utf8(symbols.label(*id)).unwrap(), id),
+      }
+      child.print(symbols, indent + 1);
+    }
+  }
+}
+
+/// exact match, single label wildcard and deep wildcards for a host
+type Matches<'a, V> = (Option<&'a KeyValue<Key,V>>, Option<&'a KeyValue<Key,V>>, Vec<&'a KeyValue<Key,V>>);
+
+pub struct InternedTrie<V> {
+  root:    Node<V>,
+  symbols: Symbols,
+  /// number of exact keys, single label wildcards and deep wildcards, to
+  /// skip the wildcard children when there are none
+  counts:  [usize; 3],
+}
+
+impl<V: Debug> InternedTrie<V> {
+  pub fn root() -> InternedTrie<V> {
+    InternedTrie {
+      root:    Node::new(),
+      symbols: Symbols::new(),
+      counts:  [0; 3],
+    }
+  }
+
+  /// ids of the labels of the key, from the last one, None if one of them
+  /// is unknown
+  fn ids(&self, key: &[u8]) -> Option<Vec<u32>> {
+    key.rsplit(|&c| c == b'.').map(|label| self.symbols.find(label)).collect()
+  }
+
+  /// inserts a lowercased key
+  pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
+    if key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
+
+    let exists = self.ids(&key)
+      .and_then(|ids| self.root.find(&ids).map(|node| node.key_value.is_some()))
+      .unwrap_or(false);
+    if exists {
+      return InsertResult::Existing;
+    }
+
+    let mut node = &mut self.root;
+    for label in key.rsplit(|&c| c == b'.') {
+      let id = self.symbols.intern(label);
+      node = node.children.entry(id).or_insert_with(Node::new);
+    }
+
+    self.counts[specificity(&key)] += 1;
+    node.key_value = Some((key, value));
+    InsertResult::Ok
+  }
+
+  /// removes the key, and returns the entry
+  pub fn remove(&mut self, key: &[u8]) -> Option<KeyValue<Key,V>> {
+    let ids = self.ids(key)?;
+    let removed = self.root.remove(&ids)?;
+
+    for id in ids {
+      self.symbols.release(id);
+    }
+    self.counts[specificity(&removed.0)] -= 1;
+    Some(removed)
+  }
+
+  /// exact match, single label wildcard and deep wildcards (shortest first)
+  /// matching the host. The walk stops at the first unknown label, after
+  /// collecting the wildcards of the domains above it
+  fn matches(&self, host: &[u8]) -> Matches<'_, V> {
+    let mut deep = Vec::new();
+    let mut single = None;
+    if host.is_empty() {
+      return (None, single, deep);
+    }
+
+    let mut node = &self.root;
+    let mut labels = host.rsplit(|&c| c == b'.').peekable();
+    while let Some(label) = labels.next() {
+      if self.counts[2] > 0 {
+        deep.extend(node.children.get(&DEEP_WILDCARD).and_then(|child| child.key_value.as_ref()));
+      }
+      // the label is the first one of the host
+      if self.counts[1] > 0 && labels.peek().is_none() {
+        single = node.children.get(&WILDCARD).and_then(|child| child.key_value.as_ref());
+      }
+
+      node = match self.symbols.find(label).and_then(|id| node.children.get(&id)) {
+        Some(child) => child,
+        None        => return (None, single, deep),
+      };
+    }
+
+    // `**.example.com` also matches `example.com`
+    if self.counts[2] > 0 {
+      deep.extend(node.children.get(&DEEP_WILDCARD).and_then(|child| child.key_value.as_ref()));
+    }
+    (node.key_value.as_ref(), single, deep)
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<'_, V> {
+    let mut entries = Vec::new();
+    self.root.collect(&mut entries);
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains, found under the node of the
+  /// domain's labels
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<'_, V> {
+    let mut entries = Vec::new();
+    let labels = if domain.first() == Some(&b'.') { &domain[1..] } else { domain };
+    let node = if labels.is_empty() {
+      Some(&self.root)
+    } else {
+      self.ids(labels).and_then(|ids| self.root.find(&ids))
+    };
+
+    if let Some(node) = node {
+      node.collect(&mut entries);
+    }
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.counts.iter().sum()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// number of labels in the symbol table, `*` and `**` included
+  pub fn symbols(&self) -> usize {
+    self.symbols.len()
+  }
+
+  pub fn print(&self) {
+    self.root.print(&self.symbols, 0);
+  }
+}
+
+impl<V: Debug> DomainLookup<V> for InternedTrie<V> {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
+    self.insert(key, value)
+  }
+
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    self.remove(key).into()
+  }
+
+  fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let (exact, single, deep) = self.matches(key);
+    exact.or(single).or_else(|| deep.last().cloned())
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let ids = self.ids(key)?;
+    let mut node = &mut self.root;
+    for id in ids.iter() {
+      node = node.children.get_mut(id)?;
+    }
+    node.key_value.as_mut().map(|kv| &mut kv.1)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let (exact, single, deep) = self.matches(key);
+    exact.into_iter().chain(single).chain(deep.into_iter().rev()).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn insert() {
+    let mut root: InternedTrie<u8> = InternedTrie::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"www.example.org"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"example.com"[..]), 4), InsertResult::Existing);
+    root.print();
+
+    // www, example, com and org are stored once, next to * and **
+    assert_eq!(root.symbols(), 6);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"WWW.Example.ORG"[..]), Some(&(b"www.example.org".to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), Some(&(b"example.com".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"www.example.net"[..]), None);
+    assert_eq!(root.domain_lookup(&b"org.example.www"[..]), None);
+  }
+
+  #[test]
+  fn remove() {
+    let mut root: InternedTrie<u8> = InternedTrie::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.symbols(), 6);
+
+    assert_eq!(root.remove(&b"test.example.com"[..]), Some((Vec::from(&b"test.example.com"[..]), 2)));
+    assert_eq!(root.remove(&b"test.example.com"[..]), None);
+    assert_eq!(root.remove(&b"example.com"[..]), None);
+    // test is not used anymore
+    assert_eq!(root.symbols(), 5);
+
+    // and its id is given to the next new label
+    assert_eq!(root.insert(Vec::from(&b"api.example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.symbols.find(&b"api"[..]), Some(5));
+    assert_eq!(root.domain_lookup(&b"api.example.com"[..]).map(|kv| kv.1), Some(3));
+
+    assert_eq!(root.remove(&b"WWW.example.com"[..]), Some((Vec::from(&b"www.example.com"[..]), 1)));
+    assert_eq!(root.remove(&b"api.example.com"[..]), Some((Vec::from(&b"api.example.com"[..]), 3)));
+    assert!(root.is_empty());
+    assert!(root.root.is_empty());
+    assert_eq!(root.symbols(), 2);
+  }
+
+  #[test]
+  fn unknown_labels() {
+    let mut root: InternedTrie<u8> = InternedTrie::root();
+
+    assert_eq!(root.insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.insert(Vec::from(&b"**.com"[..]), 3), InsertResult::Ok);
+
+    // the lookup stops at `unknown`, with the wildcards found before it
+    assert_eq!(root.domain_lookup(&b"unknown.example.com"[..]).map(|kv| kv.1), Some(2));
+    assert_eq!(root.domain_lookup(&b"www.unknown.com"[..]).map(|kv| kv.1), Some(3));
+    assert_eq!(root.domain_lookup(&b"www.example.unknown"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example..com"[..]).map(|kv| kv.1), Some(3));
+    // the table did not change
+    assert_eq!(root.symbols(), 5);
+  }
+
+  #[test]
+  fn domains() {
+    let mut root: InternedTrie<u8> = InternedTrie::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 3), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+    root.print();
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&(b"alldomains.org".to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
+    println!("after remove");
+    root.print();
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+  }
+}
@@ -0,0 +1,788 @@
+//! static trie in LOUDS encoding
+//!
+//! the trie over the reversed keys is written as bits, node by node in breadth
+//! first order: a 1 for each child, then a 0. With the rank (number of ones
+//! before a position) and select (position of the n-th zero) of those bits,
+//! the children of a node are found without any pointer: a node costs a bit
+//! for its parent's 1, a bit for its own 0, a byte for the label leading to it
+//! and a terminal bit, instead of the `Vec`s of a `TrieNode`.
+//!
+//! the bits cannot change once written, so like the exp4 fst, changes go to an
+//! overlay of inserts and tombstones, and the trie is rebuilt with `compact`,
+//! or on another thread with `compaction` and `install`. `from_entries` builds
+//! it from the `iter()` of any other structure
+
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+
+use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup, Iter, specificity, is_under, validate_key};
+
+/// bits covered by each rank directory entry
+const BLOCK: usize = 512;
+const BLOCK_WORDS: usize = BLOCK / 64;
+
+/// bit vector with a rank directory: the number of ones before each block
+struct Bits {
+  words: Vec<u64>,
+  len:   usize,
+  ranks: Vec<u32>,
+}
+
+impl Bits {
+  fn new() -> Bits {
+    Bits {
+      words: Vec::new(),
+      len:   0,
+      ranks: Vec::new(),
+    }
+  }
+
+  fn push(&mut self, bit: bool) {
+    // the last word is full
+    if self.len == self.words.len() * 64 {
+      self.words.push(0);
+    }
+    if bit {
+      *self.words.last_mut().unwrap() |= 1u64 << (self.len % 64);
+    }
+    self.len += 1;
+  }
+
+  /// writes the rank directory, once every bit is pushed
+  fn finish(&mut self) {
+    let mut ones = 0;
+    self.ranks = Vec::with_capacity(self.words.len() / BLOCK_WORDS + 1);
+    for (i, word) in self.words.iter().enumerate() {
+      if i % BLOCK_WORDS == 0 {
+        self.ranks.push(ones);
+      }
+      ones += word.count_ones();
+    }
+    self.ranks.push(ones);
+    self.words.shrink_to_fit();
+  }
+
+  fn get(&self, i: usize) -> bool {
+    self.words[i / 64] & (1u64 << (i % 64)) != 0
+  }
+
+  /// number of ones before position `i`
+  fn rank1(&self, i: usize) -> usize {
+    let block = i / BLOCK;
+    let mut rank = self.ranks[block] as usize;
+    for word in &self.words[block * BLOCK_WORDS..i / 64] {
+      rank += word.count_ones() as usize;
+    }
+    let bit = i % 64;
+    if bit != 0 {
+      rank += (self.words[i / 64] & ((1u64 << bit) - 1)).count_ones() as usize;
+    }
+    rank
+  }
+
+  /// position of the zero that has `n` zeros before it
+  fn select0(&self, n: usize) -> usize {
+    let zeros_before = |block: usize| block * BLOCK - self.ranks[block] as usize;
+
+    // last block starting with at most n zeros before it
+    // the directory has an entry per block, then the total
+    let (mut low, mut high) = (0, self.ranks.len() - 1);
+    while low + 1 < high {
+      let middle = (low + high) / 2;
+      if zeros_before(middle) <= n {
+        low = middle;
+      } else {
+        high = middle;
+      }
+    }
+
+    let mut n = n - zeros_before(low);
+    for (i, word) in self.words.iter().enumerate().skip(low * BLOCK_WORDS) {
+      let mut zeros = !word;
+      let count = zeros.count_ones() as usize;
+      if n < count {
+        for _ in 0..n {
+          zeros &= zeros - 1;
+        }
+        return i * 64 + zeros.trailing_zeros() as usize;
+      }
+      n -= count;
+    }
+    panic!("select0: there are not {} zeros", n + 1);
+  }
+
+  /// position of the first zero from position `i`
+  fn next_zero(&self, i: usize) -> usize {
+    let mut word = !self.words[i / 64] >> (i % 64);
+    if word != 0 {
+      return i + word.trailing_zeros() as usize;
+    }
+
+    let mut index = i / 64 + 1;
+    loop {
+      word = !self.words[index];
+      if word != 0 {
+        return index * 64 + word.trailing_zeros() as usize;
+      }
+      index += 1;
+    }
+  }
+
+  fn size(&self) -> usize {
+    self.words.len() * mem::size_of::<u64>() + self.ranks.len() * mem::size_of::<u32>()
+  }
+}
+
+/// exact match, single label wildcard and deep wildcards for a host
+type Matches<'a, V> = (Option<&'a KeyValue<Key,V>>, Option<&'a KeyValue<Key,V>>, Vec<&'a KeyValue<Key,V>>);
+
+/// the encoded trie. Node 0 is the root, and the nodes are numbered in
+/// breadth first order, so the children of a node have consecutive numbers
+pub struct Louds<V> {
+  /// `10` for a virtual node above the root, then a 1 per child and a 0 for
+  /// each node
+  bits:      Bits,
+  /// byte leading to each node, sorted among siblings. The root has none
+  labels:    Vec<u8>,
+  /// nodes ending a key
+  terminals: Bits,
+  /// entries of the terminal nodes, in node order. A removed entry is taken
+  /// out, and the overlay hides its node until the next compaction
+  entries:   Vec<Option<KeyValue<Key,V>>>,
+  /// number of exact keys, single label wildcards and deep wildcards, to
+  /// skip the wildcard children when there are none
+  counts:    [usize; 3],
+}
+
+impl<V> Louds<V> {
+  /// lays out the entries, and returns the keys that were left out: with
+  /// `InsertResult::Existing` for duplicates (the first one is kept),
+  /// `InsertResult::Failed` for malformed keys
+  pub fn build(entries: Vec<(Key, V)>) -> (Louds<V>, Vec<(Key, InsertResult)>) {
+    let mut skipped = Vec::new();
+    let mut valid = Vec::with_capacity(entries.len());
+    for (mut key, value) in entries {
+      key.make_ascii_lowercase();
+      match validate_key(&key) {
+        Ok(())   => valid.push((key, value)),
+        Err(e)   => skipped.push((key, InsertResult::Failed(e))),
+      }
+    }
+
+    // stable sort, so the first inserted of duplicate keys is kept
+    valid.sort_by(|a, b| a.0.iter().rev().cmp(b.0.iter().rev()));
+    let mut sorted: Vec<Option<KeyValue<Key,V>>> = Vec::with_capacity(valid.len());
+    for (key, value) in valid {
+      let duplicate = sorted.last().map(|kv| kv.as_ref().unwrap().0 == key).unwrap_or(false);
+      if duplicate {
+        skipped.push((key, InsertResult::Existing));
+      } else {
+        sorted.push(Some((key, value)));
+      }
+    }
+
+    let mut louds = Louds {
+      bits:      Bits::new(),
+      labels:    vec![0],
+      terminals: Bits::new(),
+      entries:   Vec::with_capacity(sorted.len()),
+      counts:    [0; 3],
+    };
+    louds.bits.push(true);
+    louds.bits.push(false);
+
+    // byte of the key at this depth, reading the key from its end
+    let byte = |kv: &Option<KeyValue<Key,V>>, depth: usize| {
+      let key = &kv.as_ref().unwrap().0;
+      key[key.len() - 1 - depth]
+    };
+
+    // ranges of sorted keys sharing their last `depth` bytes, one per node
+    let mut queue = VecDeque::new();
+    queue.push_back((0, sorted.len(), 0));
+    while let Some((start, end, depth)) = queue.pop_front() {
+      let mut i = start;
+
+      // a key ending at this node sorts before the longer ones
+      let terminal = i < end && sorted[i].as_ref().unwrap().0.len() == depth;
+      louds.terminals.push(terminal);
+      if terminal {
+        let kv = sorted[i].take().unwrap();
+        louds.counts[specificity(&kv.0)] += 1;
+        louds.entries.push(Some(kv));
+        i += 1;
+      }
+
+      while i < end {
+        let label = byte(&sorted[i], depth);
+        let mut j = i + 1;
+        while j < end && byte(&sorted[j], depth) == label {
+          j += 1;
+        }
+
+        louds.bits.push(true);
+        louds.labels.push(label);
+        queue.push_back((i, j, depth + 1));
+        i = j;
+      }
+      louds.bits.push(false);
+    }
+
+    louds.bits.finish();
+    louds.terminals.finish();
+    louds.labels.shrink_to_fit();
+    (louds, skipped)
+  }
+
+  /// child of the node for this byte
+  fn child(&self, node: usize, byte: u8) -> Option<usize> {
+    // the children's ones follow the zero of the previous node
+    let start = self.bits.select0(node) + 1;
+    let end = self.bits.next_zero(start);
+    // a one's rank is the number of the node it leads to
+    let first = self.bits.rank1(start);
+
+    self.labels[first..first + end - start].binary_search(&byte).ok().map(|i| first + i)
+  }
+
+  fn entry(&self, node: usize) -> Option<&KeyValue<Key,V>> {
+    if self.terminals.get(node) {
+      self.entries[self.terminals.rank1(node)].as_ref()
+    } else {
+      None
+    }
+  }
+
+  /// node of the key, whatever its case
+  fn find(&self, key: &[u8]) -> Option<usize> {
+    let mut node = 0;
+    for c in key.iter().rev() {
+      node = self.child(node, c.to_ascii_lowercase())?;
+    }
+    Some(node)
+  }
+
+  fn get(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    self.find(key).and_then(|node| self.entry(node))
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    self.entry_mut(key)?.as_mut().map(|kv| &mut kv.1)
+  }
+
+  /// takes the entry of the key out, its node stays in the trie
+  fn take(&mut self, key: &[u8]) -> Option<KeyValue<Key,V>> {
+    self.entry_mut(key)?.take()
+  }
+
+  fn entry_mut(&mut self, key: &[u8]) -> Option<&mut Option<KeyValue<Key,V>>> {
+    let node = self.find(key)?;
+    if self.terminals.get(node) {
+      let index = self.terminals.rank1(node);
+      Some(&mut self.entries[index])
+    } else {
+      None
+    }
+  }
+
+  /// exact match, single label wildcard and deep wildcards (shortest first)
+  /// matching the host. The wildcards are looked for at the root and after
+  /// each dot, where the rest of the host is its first labels
+  fn matches(&self, host: &[u8]) -> Matches<'_, V> {
+    let mut single = None;
+    let mut deep = Vec::new();
+    if host.is_empty() {
+      return (None, single, deep);
+    }
+
+    let wildcards = self.counts[1] + self.counts[2] > 0;
+    if wildcards {
+      self.wildcards(0, host, &mut single, &mut deep);
+    }
+
+    let mut node = 0;
+    for (i, c) in host.iter().enumerate().rev() {
+      node = match self.child(node, c.to_ascii_lowercase()) {
+        Some(child) => child,
+        None        => return (None, single, deep),
+      };
+      if wildcards && *c == b'.' {
+        self.wildcards(node, &host[..i], &mut single, &mut deep);
+      }
+    }
+
+    // `**.example.com` also matches `example.com`
+    if self.counts[2] > 0 {
+      let entry = self.child(node, b'.')
+        .and_then(|dot| self.child(dot, b'*'))
+        .and_then(|star| self.child(star, b'*'))
+        .and_then(|deep| self.entry(deep));
+      deep.extend(entry);
+    }
+    (self.entry(node), single, deep)
+  }
+
+  fn wildcards<'a>(&'a self, node: usize, rest: &[u8], single: &mut Option<&'a KeyValue<Key,V>>, deep: &mut Vec<&'a KeyValue<Key,V>>) {
+    if let Some(star) = self.child(node, b'*') {
+      if self.counts[1] > 0 && !rest.contains(&b'.') {
+        *single = self.entry(star);
+      }
+      if self.counts[2] > 0 {
+        deep.extend(self.child(star, b'*').and_then(|deep| self.entry(deep)));
+      }
+    }
+  }
+
+  /// number of nodes, the root included
+  pub fn nodes(&self) -> usize {
+    self.labels.len()
+  }
+
+  /// bytes used by the trie, counted like exp3's `TrieNode::size`, with the
+  /// keys of the entries
+  pub fn size(&self) -> usize {
+    mem::size_of::<Louds<V>>()
+      + self.bits.size()
+      + self.terminals.size()
+      + self.labels.len()
+      + self.entries.len() * mem::size_of::<Option<KeyValue<Key,V>>>()
+      + self.entries.iter().flatten().fold(0, |acc, kv| acc + kv.0.len())
+  }
+}
+
+/// change applied over the trie, tagged with the generation at which it
+/// happened
+pub enum Delta<V> {
+  Insert(u64, KeyValue<Key,V>),
+  Tombstone(u64),
+}
+
+impl<V> Delta<V> {
+  fn generation(&self) -> u64 {
+    match *self {
+      Delta::Insert(generation, _) => generation,
+      Delta::Tombstone(generation) => generation,
+    }
+  }
+}
+
+/// entries to rebuild the trie from, taken at a given generation
+pub struct Compaction<V> {
+  entries:    Vec<(Key, V)>,
+  generation: u64,
+}
+
+/// a rebuilt trie, ready to be installed
+pub struct Compacted<V> {
+  louds:      Louds<V>,
+  generation: u64,
+}
+
+impl<V> Compaction<V> {
+  pub fn build(self) -> Compacted<V> {
+    let (louds, _skipped) = Louds::build(self.entries);
+
+    Compacted {
+      louds,
+      generation: self.generation,
+    }
+  }
+}
+
+pub struct LoudsTrie<V> {
+  louds:      Louds<V>,
+  overlay:    HashMap<Key, Delta<V>>,
+  generation: u64,
+}
+
+impl<V> LoudsTrie<V> {
+  pub fn root() -> LoudsTrie<V> {
+    LoudsTrie {
+      louds:      Louds::build(Vec::new()).0,
+      overlay:    HashMap::new(),
+      generation: 0,
+    }
+  }
+
+  /// number of inserts and tombstones waiting for the next compaction
+  pub fn overlay_len(&self) -> usize {
+    self.overlay.len()
+  }
+
+  /// the encoded trie, without the overlay
+  pub fn louds(&self) -> &Louds<V> {
+    &self.louds
+  }
+
+  /// bytes used by the encoded trie, see `Louds::size`
+  pub fn size(&self) -> usize {
+    self.louds.size()
+  }
+
+  /// every entry: the trie entries that the overlay did not change, then the
+  /// overlay inserts
+  pub fn iter(&self) -> Iter<'_, V> {
+    Iter::new(self.entries())
+  }
+
+  /// entries for the domain and its subdomains, found by filtering every entry
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<'_, V> {
+    let mut entries = self.entries();
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  fn entries(&self) -> Vec<&KeyValue<Key,V>> {
+    let mut entries: Vec<&KeyValue<Key,V>> = self.louds.entries.iter()
+      .flatten()
+      .filter(|kv| !self.overlay.contains_key(&kv.0))
+      .collect();
+    for delta in self.overlay.values() {
+      if let Delta::Insert(_, ref kv) = *delta {
+        entries.push(kv);
+      }
+    }
+    entries
+  }
+
+  /// exact lookup of a lowercased key, checking the overlay before the trie
+  fn get(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    match self.overlay.get(key) {
+      Some(Delta::Insert(_, kv)) => Some(kv),
+      Some(Delta::Tombstone(_))  => None,
+      None                       => self.louds.get(key),
+    }
+  }
+
+  /// rebuilds the trie with the overlay merged in
+  pub fn compact(&mut self) {
+    let louds = mem::replace(&mut self.louds, Louds::build(Vec::new()).0);
+    let mut overlay = HashMap::new();
+    mem::swap(&mut overlay, &mut self.overlay);
+
+    let mut entries: Vec<(Key, V)> = louds.entries.into_iter()
+      .flatten()
+      .filter(|kv| !overlay.contains_key(&kv.0))
+      .collect();
+    for (_, delta) in overlay.drain() {
+      if let Delta::Insert(_, kv) = delta {
+        entries.push(kv);
+      }
+    }
+
+    let compaction = Compaction { entries, generation: self.generation };
+    self.install(compaction.build());
+  }
+
+  /// replaces the trie with one built by `Compaction::build`. The overlay
+  /// entries created after the compaction was started are kept
+  pub fn install(&mut self, compacted: Compacted<V>) {
+    let generation = compacted.generation;
+
+    self.louds = compacted.louds;
+    self.overlay.retain(|_, delta| delta.generation() > generation);
+  }
+}
+
+impl<V: Clone> LoudsTrie<V> {
+  /// builds the trie from the entries of another structure, as listed by
+  /// its `iter()`, and returns the keys that were left out
+  pub fn from_entries<'a, I>(entries: I) -> (LoudsTrie<V>, Vec<(Key, InsertResult)>)
+    where I: IntoIterator<Item=(&'a Key, &'a V)>, V: 'a {
+    let entries = entries.into_iter().map(|(key, value)| (key.clone(), value.clone())).collect();
+    let (louds, skipped) = Louds::build(entries);
+
+    (LoudsTrie { louds, overlay: HashMap::new(), generation: 0 }, skipped)
+  }
+
+  /// copies the current entries so the trie can be rebuilt without holding
+  /// on to it
+  pub fn compaction(&self) -> Compaction<V> {
+    let entries = self.entries().into_iter().cloned().collect();
+    Compaction { entries, generation: self.generation }
+  }
+}
+
+/// keys that can match the host, from the most specific to the least
+/// specific, to look up one by one when the overlay has changes
+fn candidates(host: &[u8]) -> Vec<Key> {
+  let mut keys = vec![host.to_vec()];
+
+  match host.iter().position(|&c| c == b'.') {
+    // `.example.com` is matched by `*.example.com`, like in the static trie
+    Some(pos) => {
+      let mut key = vec![b'*'];
+      key.extend_from_slice(&host[pos..]);
+      keys.push(key);
+    },
+    None => keys.push(vec![b'*']),
+  }
+
+  let mut domain = host;
+  loop {
+    let mut key = Vec::from(&b"**."[..]);
+    key.extend_from_slice(domain);
+    keys.push(key);
+
+    match domain.iter().position(|&c| c == b'.') {
+      Some(pos) => domain = &domain[pos + 1..],
+      None      => break,
+    }
+  }
+  keys.push(Vec::from(&b"**"[..]));
+  keys
+}
+
+impl<V> DomainLookup<V> for LoudsTrie<V> {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
+    if self.get(&key).is_some() {
+      return InsertResult::Existing;
+    }
+
+    self.generation += 1;
+    self.overlay.insert(key.clone(), Delta::Insert(self.generation, (key, value)));
+    InsertResult::Ok
+  }
+
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let key = key.to_ascii_lowercase();
+    let removed = match self.overlay.remove(&key) {
+      Some(Delta::Insert(_, kv)) => kv,
+      Some(Delta::Tombstone(generation)) => {
+        self.overlay.insert(key, Delta::Tombstone(generation));
+        return RemoveResult::NotFound;
+      },
+      // the value leaves the trie, the tombstone hides its node
+      None => match self.louds.take(&key) {
+        Some(kv) => kv,
+        None     => return RemoveResult::NotFound,
+      },
+    };
+
+    // the tombstone is kept even if the key is not in the trie, because a
+    // compaction running in the background could have copied the insert
+    self.generation += 1;
+    self.overlay.insert(key, Delta::Tombstone(self.generation));
+    RemoveResult::Ok(removed.1)
+  }
+
+  /// without changes waiting in the overlay, the trie is walked without
+  /// allocating. Otherwise the host is lowercased, and each key that could
+  /// match it is built and looked up in the overlay then in the trie: those
+  /// lookups allocate until the next `compact`
+  fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    if self.overlay.is_empty() {
+      let (exact, single, deep) = self.louds.matches(key);
+      return exact.or(single).or_else(|| deep.last().cloned());
+    }
+
+    self.domain_lookup_all(key).into_iter().next()
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let key = key.to_ascii_lowercase();
+    match self.overlay.get_mut(&key) {
+      Some(&mut Delta::Insert(_, ref mut kv)) => return Some(&mut kv.1),
+      Some(&mut Delta::Tombstone(_))          => return None,
+      None                                    => {},
+    }
+
+    // the values are not part of the encoding, they change in place
+    self.louds.get_mut(&key)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    if self.overlay.is_empty() {
+      let (exact, single, deep) = self.louds.matches(key);
+      return exact.into_iter().chain(single).chain(deep.into_iter().rev()).collect();
+    }
+
+    if key.is_empty() {
+      return Vec::new();
+    }
+    candidates(&key.to_ascii_lowercase()).iter().filter_map(|key| self.get(key)).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use InsertError;
+  use sozu_trie::TrieNode;
+
+  #[test]
+  fn bits() {
+    let mut bits = Bits::new();
+    for i in 0..2000 {
+      bits.push(i % 3 == 0);
+    }
+    bits.finish();
+
+    assert!(bits.get(999));
+    assert!(!bits.get(1000));
+    assert_eq!(bits.rank1(0), 0);
+    assert_eq!(bits.rank1(1), 1);
+    assert_eq!(bits.rank1(1000), 334);
+    assert_eq!(bits.rank1(2000), 667);
+    assert_eq!(bits.select0(0), 1);
+    assert_eq!(bits.select0(1), 2);
+    assert_eq!(bits.select0(1000), 1501);
+    assert_eq!(bits.next_zero(999), 1000);
+    assert_eq!(bits.next_zero(1000), 1000);
+  }
+
+  #[test]
+  fn build() {
+    let (louds, skipped) = Louds::build(vec![
+      (Vec::from(&b"www.example.com"[..]), 1),
+      (Vec::from(&b"example.com"[..]), 2),
+      (Vec::from(&b"WWW.example.com"[..]), 3),
+      (Vec::from(&b"*.example.org"[..]), 4),
+      (Vec::from(&b"www.*.org"[..]), 5),
+    ]);
+
+    assert_eq!(skipped, vec![
+      (Vec::from(&b"www.*.org"[..]), InsertResult::Failed(InsertError::InvalidPattern)),
+      (Vec::from(&b"www.example.com"[..]), InsertResult::Existing),
+    ]);
+    // "moc.elpmaxe" and "gro.elpmaxe.*" share nothing, then "www." follows
+    // "moc.elpmaxe"
+    assert_eq!(louds.nodes(), 1 + 11 + 13 + 4);
+    assert_eq!(louds.entries.len(), 3);
+    assert_eq!(louds.get(&b"www.example.com"[..]), Some(&(Vec::from(&b"www.example.com"[..]), 1)));
+    assert_eq!(louds.get(&b"Example.com"[..]), Some(&(Vec::from(&b"example.com"[..]), 2)));
+    assert_eq!(louds.get(&b"xample.com"[..]), None);
+    assert_eq!(louds.get(&b"ww.example.com"[..]), None);
+
+    let (louds, skipped) = Louds::<u8>::build(Vec::new());
+    assert!(skipped.is_empty());
+    assert_eq!(louds.nodes(), 1);
+    assert_eq!(louds.get(&b"example.com"[..]), None);
+  }
+
+  #[test]
+  fn from_entries() {
+    let mut sozu = TrieNode::root();
+    assert_eq!(sozu.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(sozu.domain_insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(sozu.domain_insert(Vec::from(&b"**.example.org"[..]), 3), InsertResult::Ok);
+    assert_eq!(sozu.domain_insert(Vec::from(&b"**.www.example.org"[..]), 4), InsertResult::Ok);
+    assert_eq!(sozu.domain_insert(Vec::from(&b"*"[..]), 5), InsertResult::Ok);
+
+    let (root, skipped) = LoudsTrie::from_entries(sozu.iter());
+    assert!(skipped.is_empty());
+    assert_eq!(root.len(), 5);
+    assert_eq!(root.overlay_len(), 0);
+
+    for host in [
+      &b"www.example.com"[..], &b"test.example.com"[..], &b"a.test.example.com"[..],
+      &b"example.org"[..], &b"www.example.org"[..], &b"a.www.example.org"[..],
+      &b"localhost"[..], &b"example.net"[..],
+    ].iter() {
+      assert_eq!(root.domain_lookup(host), sozu.domain_lookup(host), "{}", ::std::str::from_utf8(host).unwrap());
+    }
+    assert_eq!(root.domain_lookup_all(&b"a.www.example.org"[..]).iter().map(|kv| kv.1).collect::<Vec<_>>(), vec![4, 3]);
+  }
+
+  #[test]
+  fn background_compaction() {
+    let mut root: LoudsTrie<u8> = LoudsTrie::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 2), InsertResult::Ok);
+    root.compact();
+    assert_eq!(root.overlay_len(), 0);
+
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 3), InsertResult::Ok);
+    let compaction = root.compaction();
+    let handle = ::std::thread::spawn(move || compaction.build());
+
+    // changes made while the trie is rebuilt
+    assert_eq!(root.domain_remove(&Vec::from(&b"hello.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+
+    root.install(handle.join().unwrap());
+    assert_eq!(root.overlay_len(), 2);
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]).map(|kv| kv.1), Some(3));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]).map(|kv| kv.1), Some(4));
+    assert_eq!(root.domain_lookup(&b"hello.com"[..]), None);
+
+    root.compact();
+    assert_eq!(root.overlay_len(), 0);
+    assert_eq!(root.len(), 3);
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]).map(|kv| kv.1), Some(3));
+    assert_eq!(root.domain_lookup(&b"hello.com"[..]), None);
+  }
+
+  #[test]
+  fn domains() {
+    let mut root: LoudsTrie<u8> = LoudsTrie::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
+    root.compact();
+
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&(b"alldomains.org".to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b".alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    // the same with the overlay
+    assert_eq!(root.domain_lookup(&b".alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    root.compact();
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&(b"*.alldomains.org".to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+  }
+
+  #[test]
+  fn iter() {
+    let mut root: LoudsTrie<u8> = LoudsTrie::root();
+    let values = |root: &LoudsTrie<u8>| {
+      let mut values: Vec<u8> = root.iter().map(|(_, value)| *value).collect();
+      values.sort();
+      values
+    };
+
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 3), InsertResult::Ok);
+    root.compact();
+
+    // the entries of the trie are listed once compacted
+    assert_eq!(root.len(), 3);
+    assert_eq!(values(&root), vec![1, 2, 3]);
+    assert_eq!(root.iter_under(&b"Example.COM"[..]).count(), 2);
+
+    // and the overlay changes replace them
+    assert_eq!(root.domain_remove(&Vec::from(&b"example.org"[..])), RemoveResult::Ok(3));
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 4), InsertResult::Ok);
+    assert_eq!(root.len(), 3);
+    assert_eq!(values(&root), vec![1, 2, 4]);
+  }
+}
@@ -5,7 +5,7 @@
 use std::{iter,str};
 use std::fmt::Debug;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, is_under, validate_key};
 
 #[derive(Debug,PartialEq)]
 pub struct TrieNode<V> {
@@ -33,13 +33,14 @@ impl<V:Debug> TrieNode<V> {
 
   pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
     let res = self.insert_recursive(&key, &key, value);
-    assert_ne!(res, InsertResult::Failed);
     //println!("adding {}", str::from_utf8(&key).unwrap());
     res
   }
 
   pub fn insert_recursive(&mut self, partial_key: &[u8], key: &Key, value: V) -> InsertResult {
-    assert_ne!(partial_key, &b""[..]);
+    if partial_key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
 
     let mut found = None;
     for (index, ref child_key) in self.keys.iter().enumerate() {
@@ -122,11 +123,12 @@ impl<V:Debug> TrieNode<V> {
     return InsertResult::Ok;
   }
 
-  pub fn remove(&mut self, key: &Key) -> RemoveResult {
+  /// removes the reversed key, and returns the entry
+  pub fn remove(&mut self, key: &Key) -> Option<KeyValue<Key,V>> {
     self.remove_recursive(key)
   }
 
-  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult {
+  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> Option<KeyValue<Key,V>> {
     assert_ne!(partial_key, &b""[..]);
 
     let mut found = None;
@@ -151,35 +153,42 @@ impl<V:Debug> TrieNode<V> {
     }
 
     if let Some(index) = found {
-      if self.children[index].children.len() > 0 && self.children[index].key_value.is_some() {
-        self.children[index].key_value = None;
-        return RemoveResult::Ok;
+      // an inner node without a value: the key was not inserted, its
+      // children stay in place
+      if self.children[index].key_value.is_none() {
+        None
+      } else if !self.children[index].children.is_empty() {
+        self.children[index].key_value.take()
       } else {
         self.keys.remove(index);
-        self.children.remove(index);
+        let child = self.children.remove(index);
 
         // we might get into a case where there's only one child, and we could merge it
         // with the parent, but this case is not handle right now
-        return RemoveResult::Ok;
+        child.key_value
       }
     } else {
-      RemoveResult::NotFound
+      None
     }
   }
 
   // specific version that will handle wildcard domains
-  pub fn domain_lookup_recursive(&self, partial_key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    assert_ne!(partial_key, &b""[..]);
+  // the host is matched from its end, `host` is the part not matched yet
+  pub fn domain_lookup_recursive(&self, host: &[u8]) -> Option<&KeyValue<Key,V>> {
+    // an empty host cannot match any key
+    if host.is_empty() {
+      return None;
+    }
 
     for (index, ref child_key) in self.keys.iter().enumerate() {
-      let pos = partial_key.iter().zip(child_key.iter()).position(|(&a,&b)| a != b);
+      let pos = host.iter().rev().zip(child_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
       match pos {
         Some(0) => continue,
         Some(i) => {
           // check for wildcard
           if i+1 == child_key.len() && child_key[i] == '*' as u8 {
             let c = '.' as u8;
-            if (&partial_key[i..]).contains(&c) {
+            if (&host[..host.len() - i]).contains(&c) {
               return None;
             } else {
               return self.children[index].key_value.as_ref();
@@ -189,9 +198,9 @@ impl<V:Debug> TrieNode<V> {
           }
         },
         None    => {
-          if partial_key.len() > child_key.len() {
-            return self.children[index].domain_lookup_recursive(&partial_key[child_key.len()..]);
-          } else if partial_key.len() == child_key.len() {
+          if host.len() > child_key.len() {
+            return self.children[index].domain_lookup_recursive(&host[..host.len() - child_key.len()]);
+          } else if host.len() == child_key.len() {
             return self.children[index].key_value.as_ref();
           } else {
             return None;
@@ -203,6 +212,70 @@ impl<V:Debug> TrieNode<V> {
     None
   }
 
+  // exact version of `domain_lookup_recursive`, matching the key from its end
+  fn get_mut_recursive(&mut self, key: &[u8]) -> Option<&mut V> {
+    if key.is_empty() {
+      return self.key_value.as_mut().map(|kv| &mut kv.1);
+    }
+
+    let index = self.keys.iter().position(|child_key| {
+      child_key.len() <= key.len() &&
+        key.iter().rev().zip(child_key.iter()).all(|(a,b)| a.eq_ignore_ascii_case(b))
+    })?;
+    let rest = &key[..key.len() - self.keys[index].len()];
+    self.children[index].get_mut_recursive(rest)
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains. They are stored under the
+  /// reversed domain, so only that subtree is visited
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    if domain.is_empty() {
+      return self.iter();
+    }
+
+    let mut entries = Vec::new();
+    self.collect_under(domain, &mut entries);
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.children.iter().fold(self.key_value.iter().count(), |acc, c| acc + c.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.iter().all(|c| c.is_empty())
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.iter() {
+      child.collect(entries);
+    }
+  }
+
+  // the domain is matched from its end, like hosts in lookups
+  fn collect_under<'a>(&'a self, domain: &[u8], entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    for (child_key, child) in self.keys.iter().zip(self.children.iter()) {
+      if domain.iter().rev().zip(child_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+        continue;
+      }
+
+      if domain.len() <= child_key.len() {
+        child.collect(entries);
+      } else {
+        child.collect_under(&domain[..domain.len() - child_key.len()], entries);
+      }
+    }
+  }
+
   pub fn print(&self) {
     self.print_recursive(b"", 0)
   }
@@ -225,24 +298,31 @@ impl<V:Debug> TrieNode<V> {
 
 impl<V: Debug> DomainLookup<V> for TrieNode<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     let mut partial_key = key.clone();
     partial_key.reverse();
     self.insert_recursive(&partial_key, &key, value)
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
-    let mut partial_key = key.clone();
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let mut partial_key = key.to_ascii_lowercase();
     partial_key.reverse();
-    self.remove_recursive(&partial_key)
+    self.remove_recursive(&partial_key).into()
   }
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    let mut partial_key = key.to_vec();
-    partial_key.reverse();
-    self.domain_lookup_recursive(&partial_key)
+    self.domain_lookup_recursive(key)
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    self.get_mut_recursive(key)
   }
 }
 
@@ -281,14 +361,15 @@ mod tests {
 
     println!("before remove");
     root.print();
-    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), Some((Vec::from(&b"abce"[..]), 2)));
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), None);
     println!("after remove");
     root.print();
     println!("expected");
     root2.print();
     assert_eq!(root, root2);
 
-    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), Some((Vec::from(&b"abgh"[..]), 3)));
     println!("after remove");
     root.print();
     println!("expected");
@@ -298,6 +379,19 @@ mod tests {
     assert_eq!(root, root3);
   }
 
+  #[test]
+  fn remove_inner_node() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"*.x.x.ab.ab"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.a.b"[..]), 2), InsertResult::Ok);
+
+    // "b" is only the node shared by both keys
+    assert_eq!(root.domain_remove(&Vec::from(&b"b"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_lookup(&b"test.x.x.ab.ab"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"test.a.b"[..]).map(|kv| kv.1), Some(2));
+  }
+
   #[test]
   fn add_child_to_leaf() {
     let mut root: TrieNode<u8> = TrieNode::root();
@@ -315,7 +409,7 @@ mod tests {
     assert_eq!(root2.insert(Vec::from(&b"abce"[..]), 2), InsertResult::Ok);
 
     root2.print();
-    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok);
+    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), Some((Vec::from(&b"abc"[..]), 3)));
 
     let mut expected: TrieNode<u8> = TrieNode::root();
 
@@ -352,7 +446,7 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
-    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
     println!("after remove");
     root.print();
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
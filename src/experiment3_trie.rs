@@ -8,7 +8,7 @@
 use std::{iter,str};
 use std::fmt::Debug;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, sort_by_specificity, most_specific, is_under, covers_subtree, validate_key};
 
 #[derive(Debug,PartialEq)]
 pub struct TrieNode<V> {
@@ -54,13 +54,14 @@ impl<V:Debug> TrieNode<V> {
     }
 
     let res = self.insert_recursive(&key, &key, value);
-    assert_ne!(res, InsertResult::Failed);
     //println!("adding {}", str::from_utf8(&key).unwrap());
     res
   }
 
   pub fn insert_recursive(&mut self, partial_key: &[u8], key: &Key, value: V) -> InsertResult {
-    assert_ne!(partial_key, &b""[..]);
+    if partial_key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
 
     /*println!("insert_recursive: partial_key={}, local_key={}",
       str::from_utf8(partial_key).unwrap(),
@@ -97,12 +98,12 @@ impl<V:Debug> TrieNode<V> {
           }
         } else {
           //partial key is smaller, so insert the new value above
-          //the current node
+          //the current node, which keeps its value and children
           let new_child = TrieNode {
             key_value:  self.key_value.take(),
             local_key:  self.local_key[partial_key.len()..].to_vec(),
-            child_keys: vec!(),
-            children:   vec!(),
+            child_keys: self.child_keys.drain(..).collect(),
+            children:   self.children.drain(..).collect(),
           };
 
           self.key_value =  Some((key.clone(), value));
@@ -140,7 +141,8 @@ impl<V:Debug> TrieNode<V> {
     return InsertResult::Ok;
   }
 
-  pub fn remove(&mut self, partial_key: &Key) -> RemoveResult {
+  /// removes the reversed key, and returns the entry
+  pub fn remove(&mut self, partial_key: &Key) -> Option<KeyValue<Key,V>> {
     /*println!("remove: partial_key={}, local_key={}",
       str::from_utf8(partial_key).unwrap(),
       str::from_utf8(&self.local_key).unwrap());
@@ -155,25 +157,25 @@ impl<V:Debug> TrieNode<V> {
           self.remove_recursive(&partial_key[local_len..])
         } else if partial_key.len() == local_len {
           if self.key_value.is_some() {
-            self.key_value = None;
+            let removed = self.key_value.take();
             if self.child_keys.is_empty() {
               self.local_key = vec!();
             }
 
-            RemoveResult::Ok
+            removed
           } else {
-            RemoveResult::NotFound
+            None
           }
         } else {
-          RemoveResult::NotFound
+          None
         }
       },
-      Some(_) => RemoveResult::NotFound
+      Some(_) => None
     }
 
   }
 
-  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult {
+  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> Option<KeyValue<Key,V>> {
     /*println!("remove_recursive: partial_key={}, local_key={}, child_keys={}",
       str::from_utf8(partial_key).unwrap(),
       str::from_utf8(&self.local_key).unwrap(),
@@ -182,7 +184,7 @@ impl<V:Debug> TrieNode<V> {
 
     assert_ne!(partial_key, &b""[..]);
     match self.child_keys.iter().position(|k| *k == partial_key[0]) {
-      None => RemoveResult::NotFound,
+      None => None,
       Some(index) => {
         let res = {
           let child = &mut self.children[index];
@@ -194,21 +196,20 @@ impl<V:Debug> TrieNode<V> {
               } else if partial_key.len() == child_local_len {
                 if child.key_value.is_some() {
         //println!("removing key_value: {:?}", child.key_value);
-                  child.key_value = None;
-                  RemoveResult::Ok
+                  child.key_value.take()
                 } else {
-                  RemoveResult::NotFound
+                  None
                 }
               } else {
-                RemoveResult::NotFound
+                None
               }
             },
-            Some(_) => RemoveResult::NotFound
+            Some(_) => None
           }
         };
 
         // we might have some cleanup to do
-        if res == RemoveResult::Ok {
+        if res.is_some() {
           /*println!("will cleanup. child has key_value:{} child has child_keys:{}",
           self.children[index].key_value.is_some(),
           self.children[index].child_keys.len());
@@ -222,15 +223,13 @@ impl<V:Debug> TrieNode<V> {
           }
 
           if self.child_keys.len() == 1 {
-            match (self.key_value.is_some(), self.children[0].key_value.is_some()) {
-              // we keep the child because both current node and child have values
-              (true, true) => return res,
-              // we take the child's value
-              (false, true) => {
-                self.key_value = self.children[0].key_value.take();
-              },
-              _ => { }
+            // we keep the child when the current node has a value: merging
+            // would append the child's local key to the key of that value
+            if self.key_value.is_some() {
+              return res;
             }
+            // we take the child's value
+            self.key_value = self.children[0].key_value.take();
 
             let _k = self.child_keys.remove(0);
             let mut child = self.children.remove(0);
@@ -256,46 +255,223 @@ impl<V:Debug> TrieNode<V> {
     }
   }
 
+  /// removes the entries for the domain and its subdomains in one walk, and
+  /// returns them. A domain starting with a dot only covers its subdomains
+  pub fn remove_subtree(&mut self, domain: &[u8]) -> Vec<KeyValue<Key,V>> {
+    let mut removed = Vec::new();
+    if self.remove_subtree_recursive(domain, domain, &mut removed) {
+      ::std::mem::replace(self, TrieNode::root()).into_entries(&mut removed);
+    } else if self.key_value.is_none() && self.child_keys.is_empty() {
+      *self = TrieNode::root();
+    }
+    removed
+  }
+
+  // `rest` is the part of the domain not matched yet, from its end. Returns
+  // true if every entry of this node is under the domain, so that the parent
+  // detaches the node whole instead of visiting it
+  fn remove_subtree_recursive(&mut self, domain: &[u8], rest: &[u8], removed: &mut Vec<KeyValue<Key,V>>) -> bool {
+    if rest.iter().rev().zip(self.local_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+      return false;
+    }
+
+    if rest.len() < self.local_key.len() {
+      return covers_subtree(domain, self.local_key[rest.len()]);
+    }
+
+    if rest.len() == self.local_key.len() && self.key_value.as_ref().map(|kv| is_under(&kv.0, domain)).unwrap_or(false) {
+      removed.extend(self.key_value.take());
+    }
+
+    let rest = &rest[..rest.len() - self.local_key.len()];
+    let mut index = 0;
+    while index < self.children.len() {
+      if self.children[index].remove_subtree_recursive(domain, rest, removed) {
+        self.child_keys.remove(index);
+        self.children.remove(index).into_entries(removed);
+      } else if self.children[index].key_value.is_none() && self.children[index].child_keys.is_empty() {
+        self.child_keys.remove(index);
+        self.children.remove(index);
+      } else {
+        index += 1;
+      }
+    }
+
+    false
+  }
+
+  fn into_entries(self, entries: &mut Vec<KeyValue<Key,V>>) {
+    entries.extend(self.key_value);
+    for child in self.children {
+      child.into_entries(entries);
+    }
+  }
+
   // specific version that will handle wildcard domains
-  pub fn domain_lookup_recursive(&self, partial_key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    assert_ne!(partial_key, &b""[..]);
+  // the host is matched from its end, `host` is the part not matched yet
+  pub fn domain_lookup_recursive(&self, host: &[u8]) -> Option<&KeyValue<Key,V>> {
+    // an empty host cannot match any key
+    if host.is_empty() {
+      return None;
+    }
 
-    /*println!("domain_lookup_recursive: partial_key={}, local_key={}, child_keys={}",
-      str::from_utf8(partial_key).unwrap(),
+    /*println!("domain_lookup_recursive: host={}, local_key={}, child_keys={}",
+      str::from_utf8(host).unwrap(),
       str::from_utf8(&self.local_key).unwrap(),
       str::from_utf8(&self.child_keys).unwrap(),
     );
     */
-    let pos = partial_key.iter().zip(self.local_key.iter()).position(|(&a,&b)| a != b);
+    let pos = host.iter().rev().zip(self.local_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
     match pos {
       None => {
         let local_len = self.local_key.len();
-        if partial_key.len() > local_len {
-          match self.child_keys.iter().position(|k| *k == partial_key[local_len]) {
-            None => None,
-            Some(index) => {
-              self.children[index].domain_lookup_recursive(&partial_key[local_len..])
+        if host.len() > local_len {
+          let exact = self.child_keys.iter().position(|k| k.eq_ignore_ascii_case(&host[host.len() - 1 - local_len]))
+            .and_then(|index| self.children[index].domain_lookup_recursive(&host[..host.len() - local_len]));
+
+          // a wildcard child starts with '*', so it is not found by the next byte
+          let wildcard = self.child_keys.iter().position(|&k| k == b'*')
+            .and_then(|index| self.children[index].domain_lookup_recursive(&host[..host.len() - local_len]));
+
+          most_specific(exact, wildcard)
+        } else if host.len() == local_len {
+          self.key_value.as_ref().or_else(|| self.find_rest(local_len, b".**"))
+        } else {
+          self.find_rest(host.len(), b".**")
+        }
+      },
+      Some(i) => {
+        // check for wildcard
+        if i+1 == self.local_key.len() && self.local_key[i] == '*' as u8 {
+          let c = '.' as u8;
+          if !(&host[..host.len() - i]).contains(&c) {
+            return self.key_value.as_ref();
+          }
+        }
+
+        self.find_rest(i, b"**")
+      }
+    }
+  }
+
+  // collects every key matching the host: the child for the next byte,
+  // then the wildcard child
+  pub fn domain_lookup_all_recursive<'a>(&'a self, host: &[u8], matches: &mut Vec<&'a KeyValue<Key,V>>) {
+    // an empty host cannot match any key
+    if host.is_empty() {
+      return;
+    }
+
+    let pos = host.iter().rev().zip(self.local_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
+    match pos {
+      None => {
+        let local_len = self.local_key.len();
+        if host.len() > local_len {
+          let next = host[host.len() - 1 - local_len];
+          for (index, &k) in self.child_keys.iter().enumerate() {
+            if k.eq_ignore_ascii_case(&next) || k == b'*' {
+              self.children[index].domain_lookup_all_recursive(&host[..host.len() - local_len], matches);
             }
           }
-        } else if partial_key.len() == local_len {
-          self.key_value.as_ref()
+        } else if host.len() == local_len {
+          matches.extend(self.key_value.as_ref());
+          matches.extend(self.find_rest(local_len, b".**"));
         } else {
-          None
+          matches.extend(self.find_rest(host.len(), b".**"));
         }
       },
       Some(i) => {
         // check for wildcard
         if i+1 == self.local_key.len() && self.local_key[i] == '*' as u8 {
           let c = '.' as u8;
-          if (&partial_key[i..]).contains(&c) {
-            None
-          } else {
-            self.key_value.as_ref()
+          if !(&host[..host.len() - i]).contains(&c) {
+            matches.extend(self.key_value.as_ref());
           }
-        } else {
-          None
         }
 
+        matches.extend(self.find_rest(i, b"**"));
+      }
+    }
+  }
+
+  // deep wildcards: once the host is matched up to a label, the key of
+  // `**.domain` continues with `rest`, from `offset` in this node's local key
+  fn find_rest(&self, offset: usize, rest: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let local_key = &self.local_key[offset..];
+    if !rest.starts_with(local_key) {
+      return None;
+    }
+
+    let rest = &rest[local_key.len()..];
+    if rest.is_empty() {
+      return self.key_value.as_ref();
+    }
+
+    self.child_keys.iter().position(|&k| k == rest[0])
+      .and_then(|index| self.children[index].find_rest(0, rest))
+  }
+
+  // exact version of `domain_lookup_recursive`, matching the key from its end
+  fn get_mut_recursive(&mut self, key: &[u8]) -> Option<&mut V> {
+    if key.len() < self.local_key.len() ||
+      key.iter().rev().zip(self.local_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+      return None;
+    }
+
+    if key.len() == self.local_key.len() {
+      return self.key_value.as_mut().map(|kv| &mut kv.1);
+    }
+
+    let rest = &key[..key.len() - self.local_key.len()];
+    let next = rest[rest.len() - 1];
+    let index = self.child_keys.iter().position(|k| k.eq_ignore_ascii_case(&next))?;
+    self.children[index].get_mut_recursive(rest)
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains. They are stored under the
+  /// reversed domain, so only that subtree is visited
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect_under(domain, &mut entries);
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.children.iter().fold(self.key_value.iter().count(), |acc, c| acc + c.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.iter().all(|c| c.is_empty())
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.iter() {
+      child.collect(entries);
+    }
+  }
+
+  // the domain is matched from its end, like hosts in lookups
+  fn collect_under<'a>(&'a self, domain: &[u8], entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    if domain.iter().rev().zip(self.local_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+      return;
+    }
+
+    if domain.len() <= self.local_key.len() {
+      self.collect(entries);
+    } else {
+      let rest = &domain[..domain.len() - self.local_key.len()];
+      let next = rest[rest.len() - 1];
+      if let Some(index) = self.child_keys.iter().position(|k| k.eq_ignore_ascii_case(&next)) {
+        self.children[index].collect_under(rest, entries);
       }
     }
   }
@@ -325,7 +501,12 @@ impl<V:Debug> TrieNode<V> {
 
 impl<V: Debug> DomainLookup<V> for TrieNode<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     let mut partial_key = key.clone();
     partial_key.reverse();
 
@@ -340,17 +521,26 @@ impl<V: Debug> DomainLookup<V> for TrieNode<V> {
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
-    let mut partial_key = key.clone();
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let mut partial_key = key.to_ascii_lowercase();
     partial_key.reverse();
-    self.remove(&partial_key)
+    self.remove(&partial_key).into()
   }
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    let mut partial_key = key.to_vec();
-    partial_key.reverse();
-    self.domain_lookup_recursive(&partial_key)
+    self.domain_lookup_recursive(key)
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    self.get_mut_recursive(key)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut matches = Vec::new();
+    self.domain_lookup_all_recursive(key, &mut matches);
+    sort_by_specificity(&mut matches);
+    matches
   }
 }
 
@@ -397,7 +587,8 @@ mod tests {
 
     println!("before remove");
     root.print();
-    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), Some((Vec::from(&b"abce"[..]), 2)));
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), None);
     println!("after remove");
     root.print();
 
@@ -405,7 +596,7 @@ mod tests {
     root2.print();
     assert_eq!(root, root2);
 
-    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), Some((Vec::from(&b"abgh"[..]), 3)));
     println!("after remove");
     root.print();
     println!("expected");
@@ -441,7 +632,7 @@ mod tests {
 
     println!("root2:");
     root2.print();
-    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok);
+    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), Some((Vec::from(&b"abc"[..]), 3)));
 
     println!("root2 after,remove:");
     root2.print();
@@ -457,6 +648,20 @@ mod tests {
     assert_eq!(root2, expected);
   }
 
+  #[test]
+  fn insert_above_inner_node() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"x.ab.www"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"y.ab.www"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www"[..]), 3), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"x.ab.www"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"y.ab.www"[..]).map(|kv| kv.1), Some(2));
+    assert_eq!(root.domain_lookup(&b"www"[..]).map(|kv| kv.1), Some(3));
+  }
+
   #[test]
   fn domains() {
     let mut root: TrieNode<u8> = TrieNode::root();
@@ -481,7 +686,7 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
-    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
     println!("after remove");
     root.print();
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
@@ -489,4 +694,24 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
   }
+
+  #[test]
+  fn remove_keeps_other_routes() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"*.ab.ba.ba.www"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"com.ab.www"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"ab.a.x.www"[..]), 4), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"*.ab.ba.ba.www"[..])), RemoveResult::Ok(1));
+    println!("after remove");
+    root.print();
+    assert_eq!(root.domain_lookup(&b"test.ab.ba.ba.www"[..]), None);
+    assert_eq!(root.domain_lookup(&b"com.ab.www"[..]).map(|kv| kv.1), Some(2));
+    assert_eq!(root.domain_lookup(&b"www"[..]).map(|kv| kv.1), Some(3));
+    assert_eq!(root.domain_lookup(&b"ab.a.x.www"[..]).map(|kv| kv.1), Some(4));
+    assert_eq!(root.domain_insert(Vec::from(&b"www"[..]), 5), InsertResult::Existing);
+  }
 }
@@ -2,15 +2,29 @@
 //!
 //! this example uses a state machine that must be completely regenerated
 //! on each change, and keys must be inserted in order
+//!
+//! once the machine is finished, changes go to a small mutable overlay of
+//! inserts and tombstones that is checked before the fst. `compact` merges
+//! the overlay back into a new fst. The rebuild can also happen on another
+//! thread: `compaction` takes a copy of the entries, `Compaction::build`
+//! generates the fst, and `install` swaps it in, keeping the changes made
+//! in the meantime
+//!
+//! keys that cannot go in the fst, like duplicates, are listed in the
+//! `FinishReport` returned by `finish`
 
-use std::fmt::{Display};
-use fst::{MapBuilder,Map};
+use std::collections::HashMap;
+use fst::{self,MapBuilder,Map};
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, is_under, validate_key};
 
 pub struct Machine<V> {
-  index: Vec<KeyValue<Key,V>>,
+  /// entries of the fst keys, by position. The entry of a removed key is
+  /// taken out, and its tombstone hides the position until the next compaction
+  index: Vec<Option<KeyValue<Key,V>>>,
   map: MachineMap<V>,
+  overlay: HashMap<Key, Delta<V>>,
+  generation: u64,
 }
 
 pub enum MachineMap<V> {
@@ -18,97 +32,391 @@ pub enum MachineMap<V> {
   Map(Map),
 }
 
-impl<V: Ord+Display> Machine<V> {
+/// change applied over the finished fst, tagged with the generation
+/// at which it happened
+pub enum Delta<V> {
+  Insert(u64, KeyValue<Key,V>),
+  Tombstone(u64),
+}
+
+impl<V> Delta<V> {
+  fn generation(&self) -> u64 {
+    match *self {
+      Delta::Insert(generation, _) => generation,
+      Delta::Tombstone(generation) => generation,
+    }
+  }
+}
+
+/// entries to rebuild the fst from, taken at a given generation
+pub struct Compaction<V> {
+  entries: Vec<(Key, V)>,
+  generation: u64,
+}
+
+/// a rebuilt fst, ready to be installed in the machine
+pub struct Compacted<V> {
+  index: Vec<Option<KeyValue<Key,V>>>,
+  map: Map,
+  generation: u64,
+}
+
+/// keys that were left out of the fst when building it, with the reason:
+/// `InsertResult::Existing` for duplicates, `InsertResult::Failed` with the
+/// error otherwise
+#[derive(Debug,PartialEq)]
+pub struct FinishReport {
+  pub inserted: usize,
+  pub skipped:  Vec<(Key, InsertResult)>,
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+  AlreadyFinished,
+  /// the entries are still waiting for `finish`, there is nothing to compact
+  NotFinished,
+  Fst(fst::Error),
+}
+
+impl From<fst::Error> for BuildError {
+  fn from(e: fst::Error) -> BuildError {
+    BuildError::Fst(e)
+  }
+}
+
+impl<V> Compaction<V> {
+  pub fn build(self) -> Result<Compacted<V>, BuildError> {
+    let (index, map, _report) = build_map(self.entries)?;
+
+    Ok(Compacted {
+      index,
+      map,
+      generation: self.generation,
+    })
+  }
+}
+
+fn build_map<V>(mut v: Vec<(Key, V)>) -> Result<(Vec<Option<KeyValue<Key,V>>>, Map, FinishReport), BuildError> {
+  let mut builder = MapBuilder::memory();
+  let mut index = Vec::new();
+  let mut skipped = Vec::new();
+
+  // stable sort, so the first inserted of duplicate keys is kept
+  v.sort_by(|a, b| a.0.iter().rev().cmp(b.0.iter().rev()));
+  //v.sort();
+
+  let mut i = 0u64;
+  for (k, v) in v.drain(..) {
+    let mut key = k.to_vec();
+    key.reverse();
+
+    let duplicate = index.last().map(|kv: &Option<KeyValue<Key,V>>| kv.as_ref().unwrap().0 == k).unwrap_or(false);
+    if duplicate {
+      skipped.push((k, InsertResult::Existing));
+    } else if let Err(_e) = builder.insert(&key, i) {
+      //println!("error inserting key: {:?}", e);
+      skipped.push((k, InsertResult::Failed(InsertError::Conflict)));
+    } else {
+      //println!("inserted {} -> ({}, {})", std::str::from_utf8(&k).unwrap(),
+      //  std::str::from_utf8(&key).unwrap(), v);
+      index.push(Some((k, v)));
+      i += 1;
+    }
+  }
+
+  //println!("{} bytes written to stream", builder.bytes_written());
+  let v = builder.into_inner()?;
+  let map = Map::from_bytes(v)?;
+
+  let report = FinishReport {
+    inserted: index.len(),
+    skipped,
+  };
+
+  Ok((index, map, report))
+}
+
+impl<V> Machine<V> {
   pub fn new() -> Self {
     Machine {
       index: Vec::new(),
       map: MachineMap::Building(Vec::new()),
+      overlay: HashMap::new(),
+      generation: 0,
     }
   }
 
-  pub fn finish(&mut self) {
-    let mut builder = MapBuilder::memory();
+  pub fn finish(&mut self) -> Result<FinishReport, BuildError> {
+    let (index, map, report) = match self.map {
+      MachineMap::Map(_) => return Err(BuildError::AlreadyFinished),
+      MachineMap::Building(ref mut v) => build_map(v.drain(..).collect())?,
+    };
+
+    self.index = index;
+    self.map = MachineMap::Map(map);
+    Ok(report)
+  }
+
+  pub fn lookup(&self, key: &[u8]) -> Option<u64> {
+    let mut partial_key = key.to_vec();
+    partial_key.reverse();
 
     match self.map {
-      MachineMap::Map(_) => panic!("already finished"),
-      MachineMap::Building(ref mut v) => {
-        v.sort_by(|a, b| a.0.iter().rev().cmp(b.0.iter().rev()));
-        //v.sort();
-
-        let mut index = 0u64;
-        for (k, v) in v.drain(..) {
-          let mut key = k.to_vec();
-          key.reverse();
-
-          if let Err(_e) = builder.insert(&key, index) {
-            //println!("error inserting key: {:?}", e);
-          } else {
-            //println!("inserted {} -> ({}, {})", std::str::from_utf8(&k).unwrap(),
-            //  std::str::from_utf8(&key).unwrap(), v);
-            self.index.push((k, v));
-            index += 1;
-          }
-        }
+      MachineMap::Map(ref m) => {
+        m.get(&partial_key)
+      },
+      MachineMap::Building(_) => {
+        panic!("builder not finished");
       }
     }
+  }
 
-    //println!("{} bytes written to stream", builder.bytes_written());
-    let v = builder.into_inner().unwrap();
+  /// number of inserts and tombstones waiting for the next compaction
+  pub fn overlay_len(&self) -> usize {
+    self.overlay.len()
+  }
 
-    self.map = MachineMap::Map(Map::from_bytes(v).unwrap());
+  /// every entry: the ones waiting for `finish`, then the fst entries that
+  /// the overlay did not change, then the overlay inserts
+  pub fn iter(&self) -> Iter<V> {
+    Iter::new(self.entries())
   }
 
-  pub fn lookup(&self, key: &[u8]) -> Option<u64> {
+  /// entries for the domain and its subdomains, found by filtering every entry
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = self.entries();
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  fn entries(&self) -> Vec<&KeyValue<Key,V>> {
+    let mut entries: Vec<&KeyValue<Key,V>> = match self.map {
+      MachineMap::Building(ref v) => v.iter().collect(),
+      MachineMap::Map(_) => Vec::new(),
+    };
+    entries.extend(self.index.iter().flatten().filter(|kv| !self.overlay.contains_key(&kv.0)));
+    for delta in self.overlay.values() {
+      if let Delta::Insert(_, ref kv) = *delta {
+        entries.push(kv);
+      }
+    }
+    entries
+  }
+
+  fn base_contains(&self, key: &[u8]) -> bool {
+    let mut partial_key = key.to_vec();
+    partial_key.reverse();
+
+    match self.map {
+      MachineMap::Map(ref m) => m.get(partial_key).is_some(),
+      MachineMap::Building(_) => false,
+    }
+  }
+
+  /// exact lookup, checking the overlay before the fst
+  fn get(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    match self.overlay.get(key) {
+      Some(&Delta::Insert(_, ref kv)) => return Some(kv),
+      Some(&Delta::Tombstone(_)) => return None,
+      None => {},
+    }
+
     let mut partial_key = key.to_vec();
     partial_key.reverse();
 
+    //println!("looking up {} -> {}", std::str::from_utf8(key).unwrap(), std::str::from_utf8(&partial_key).unwrap());
+
     match self.map {
       MachineMap::Map(ref m) => {
-        m.get(&partial_key)
+        let r = m.get(partial_key);
+        //println!("res: {:?}", r);
+        r.and_then(|i| self.index.get(i as usize)).and_then(|kv| kv.as_ref())
       },
       MachineMap::Building(_) => {
         panic!("builder not finished");
       }
     }
   }
+
+  /// rebuilds the fst with the overlay merged in. Before `finish`, there is
+  /// no fst yet, and it returns `BuildError::NotFinished`
+  pub fn compact(&mut self) -> Result<(), BuildError> {
+    if let MachineMap::Building(_) = self.map {
+      return Err(BuildError::NotFinished);
+    }
+
+    let mut index = Vec::new();
+    ::std::mem::swap(&mut index, &mut self.index);
+
+    let mut overlay = HashMap::new();
+    ::std::mem::swap(&mut overlay, &mut self.overlay);
+
+    let mut entries: Vec<(Key, V)> = index.into_iter()
+      .flatten()
+      .filter(|kv| !overlay.contains_key(&kv.0))
+      .collect();
+    for (_, delta) in overlay.drain() {
+      if let Delta::Insert(_, kv) = delta {
+        entries.push(kv);
+      }
+    }
+
+    let compaction = Compaction { entries, generation: self.generation };
+    self.install(compaction.build()?);
+    Ok(())
+  }
+
+  /// replaces the fst with one built by `Compaction::build`. The overlay
+  /// entries created after the compaction was started are kept
+  pub fn install(&mut self, compacted: Compacted<V>) {
+    let generation = compacted.generation;
+
+    self.index = compacted.index;
+    self.map = MachineMap::Map(compacted.map);
+    self.overlay.retain(|_, delta| delta.generation() > generation);
+  }
+}
+
+impl<V: Clone> Machine<V> {
+  /// copies the current entries so the fst can be rebuilt without
+  /// holding on to the machine. Returns `BuildError::NotFinished` before
+  /// `finish`
+  pub fn compaction(&self) -> Result<Compaction<V>, BuildError> {
+    if let MachineMap::Building(_) = self.map {
+      return Err(BuildError::NotFinished);
+    }
+
+    let mut entries: Vec<(Key, V)> = self.index.iter()
+      .flatten()
+      .filter(|kv| !self.overlay.contains_key(&kv.0))
+      .cloned()
+      .collect();
+    for delta in self.overlay.values() {
+      if let Delta::Insert(_, ref kv) = *delta {
+        entries.push(kv.clone());
+      }
+    }
+
+    Ok(Compaction { entries, generation: self.generation })
+  }
 }
 
 impl<V> DomainLookup<V> for Machine<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
     //let mut k = key.to_vec();
     //k.reverse();
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
 
-    match self.map {
-      MachineMap::Map(_) => panic!("already finished"),
+    let in_base = match self.map {
+      MachineMap::Map(_) => self.base_contains(&key),
       MachineMap::Building(ref mut v) => {
         v.push((key, value));
+        return InsertResult::Ok;
       }
+    };
+
+    let existing = match self.overlay.get(&key) {
+      Some(&Delta::Insert(_, _)) => true,
+      Some(&Delta::Tombstone(_)) => false,
+      None => in_base,
+    };
+
+    if existing {
+      return InsertResult::Existing;
     }
 
+    self.generation += 1;
+    self.overlay.insert(key.clone(), Delta::Insert(self.generation, (key, value)));
     InsertResult::Ok
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, _key: &Key) -> RemoveResult {
-    unimplemented!()
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let key = &key.to_ascii_lowercase();
+    let position = match self.map {
+      MachineMap::Map(ref m) => {
+        let mut partial_key = key.clone();
+        partial_key.reverse();
+        m.get(partial_key)
+      },
+      MachineMap::Building(ref mut v) => {
+        return match v.iter().position(|kv| kv.0 == *key) {
+          Some(pos) => RemoveResult::Ok(v.remove(pos).1),
+          None => RemoveResult::NotFound,
+        };
+      }
+    };
+
+    let removed = match self.overlay.remove(key) {
+      Some(Delta::Insert(_, kv)) => kv,
+      Some(Delta::Tombstone(generation)) => {
+        self.overlay.insert(key.clone(), Delta::Tombstone(generation));
+        return RemoveResult::NotFound;
+      },
+      // the value leaves the index, the tombstone hides its position
+      None => match position.and_then(|i| self.index[i as usize].take()) {
+        Some(kv) => kv,
+        None => return RemoveResult::NotFound,
+      },
+    };
+
+    // the tombstone is kept even if the key is not in the base, because a
+    // compaction running in the background could have copied the insert
+    self.generation += 1;
+    self.overlay.insert(key.clone(), Delta::Tombstone(self.generation));
+    RemoveResult::Ok(removed.1)
   }
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    let mut partial_key = key.to_vec();
-    partial_key.reverse();
+    // keys are stored lowercased
+    let key = &key.to_ascii_lowercase()[..];
+    if let Some(kv) = self.get(key) {
+      return Some(kv);
+    }
 
-    //println!("looking up {} -> {}", std::str::from_utf8(key).unwrap(), std::str::from_utf8(&partial_key).unwrap());
+    // wildcards only match one label: look up the host with its first
+    // label replaced by '*'
+    match key.iter().position(|&c| c == b'.') {
+      Some(pos) if pos > 0 => {
+        let mut wildcard_key = Vec::with_capacity(key.len() - pos + 1);
+        wildcard_key.push(b'*');
+        wildcard_key.extend_from_slice(&key[pos..]);
+        self.get(&wildcard_key)
+      },
+      _ => None,
+    }
+  }
 
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let key = key.to_ascii_lowercase();
+    match self.overlay.get_mut(&key) {
+      Some(&mut Delta::Insert(_, ref mut kv)) => return Some(&mut kv.1),
+      Some(&mut Delta::Tombstone(_)) => return None,
+      None => {},
+    }
+
+    // the fst only maps keys to positions in the index, the values can
+    // change in place
     match self.map {
+      MachineMap::Building(ref mut v) => v.iter_mut().find(|kv| kv.0 == key).map(|kv| &mut kv.1),
       MachineMap::Map(ref m) => {
-        let r = m.get(partial_key);
-        //println!("res: {:?}", r);
-        r.and_then(|i| self.index.get(i as usize))
-      },
-      MachineMap::Building(_) => {
-        panic!("builder not finished");
+        let mut partial_key = key;
+        partial_key.reverse();
+        let index = m.get(partial_key)?;
+        self.index.get_mut(index as usize)?.as_mut().map(|kv| &mut kv.1)
       }
     }
   }
@@ -127,7 +435,7 @@ mod tests {
     assert_eq!(root.insert(Vec::from(&b"abce"[..]), 2), InsertResult::Ok);
     assert_eq!(root.insert(Vec::from(&b"abgh"[..]), 3), InsertResult::Ok);
 
-    root.finish();
+    root.finish().unwrap();
     //assert_eq!(root.lookup(&b"abce"[..]), Some(&((&b"abce"[..]).to_vec(), 2)));
     //assert!(false);
   }
@@ -226,16 +534,191 @@ mod tests {
     assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
     assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
 
-    root.finish();
+    root.finish().unwrap();
 
     assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
     assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
 
     assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&((&b"alldomains.org"[..]).to_vec(), 4)));
-    //assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
-    //assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
   }
+
+  #[test]
+  fn overlay() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"hello.com"[..])), RemoveResult::Ok(5));
+
+    root.finish().unwrap();
+    assert_eq!(root.domain_lookup(&b"hello.com"[..]), None);
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 3), InsertResult::Existing);
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"test.example.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_remove(&Vec::from(&b"test.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_remove(&Vec::from(&b"unknown.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.overlay_len(), 2);
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&((&b"alldomains.org"[..]).to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), None);
+
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 6), InsertResult::Ok);
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&((&b"test.example.com"[..]).to_vec(), 6)));
+
+    root.compact().unwrap();
+    assert_eq!(root.overlay_len(), 0);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&((&b"alldomains.org"[..]).to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&((&b"test.example.com"[..]).to_vec(), 6)));
+    assert_eq!(root.domain_lookup(&b"hello.com"[..]), None);
+  }
+
+  #[test]
+  fn background_compaction() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    root.finish().unwrap();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 3), InsertResult::Ok);
+
+    let compaction = root.compaction().unwrap();
+    let handle = ::std::thread::spawn(move || compaction.build().unwrap());
+
+    // changes made while the fst is rebuilt
+    assert_eq!(root.domain_remove(&Vec::from(&b"hello.com"[..])), RemoveResult::Ok(3));
+    assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
+
+    root.install(handle.join().unwrap());
+    assert_eq!(root.overlay_len(), 2);
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&((&b"test.example.com"[..]).to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&((&b"alldomains.org"[..]).to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"hello.com"[..]), None);
+  }
+
+  #[test]
+  fn duplicates() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 3), InsertResult::Ok);
+
+    let report = root.finish().unwrap();
+    assert_eq!(report, FinishReport {
+      inserted: 2,
+      skipped: vec![(Vec::from(&b"www.example.com"[..]), InsertResult::Existing)],
+    });
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+
+    match root.finish() {
+      Err(BuildError::AlreadyFinished) => {},
+      _ => panic!("the machine was already finished"),
+    }
+  }
+
+  #[test]
+  fn compact_before_finish() {
+    let mut root: Machine<u8> = Machine::new();
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+
+    // the pending entries are kept for `finish`
+    match root.compact() {
+      Err(BuildError::NotFinished) => {},
+      _ => panic!("the machine was not finished"),
+    }
+    match root.compaction() {
+      Err(BuildError::NotFinished) => {},
+      _ => panic!("the machine was not finished"),
+    }
+
+    assert_eq!(root.finish().unwrap().inserted, 1);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+  }
+
+  #[test]
+  fn mixed_case() {
+    let mut root: Machine<u8> = Machine::new();
+
+    // keys are lowercased before and after finish
+    assert_eq!(root.domain_insert(Vec::from(&b"www.EXAMPLE.com"[..]), 1), InsertResult::Ok);
+    root.finish().unwrap();
+    assert_eq!(root.domain_insert(Vec::from(&b"*.AllDomains.org"[..]), 2), InsertResult::Ok);
+
+    assert_eq!(root.domain_lookup(&b"WwW.eXaMpLe.CoM"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"TEST.ALLDOMAINS.ORG"[..]), Some(&(b"*.alldomains.org".to_vec(), 2)));
+  }
+
+  #[test]
+  fn upsert() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 1), Ok(None));
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 2), Ok(Some(1)));
+    root.finish().unwrap();
+
+    // the values in the fst index are updated in place, without the overlay
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 3), Ok(Some(2)));
+    assert_eq!(root.overlay_len(), 0);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(3));
+  }
+
+  #[test]
+  fn invalid_keys() {
+    let mut root: Machine<u8> = Machine::new();
+
+    // rejected keys are not queued until `finish`
+    assert_eq!(root.domain_insert(Vec::from(&b"www example.com"[..]), 1), InsertResult::Failed(InsertError::InvalidCharacter));
+    assert_eq!(root.domain_insert(Vec::from(&b"www..example.com"[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+
+    assert_eq!(root.finish().unwrap().skipped, vec![]);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(2));
+  }
+
+  #[test]
+  fn iter() {
+    let mut root: Machine<u8> = Machine::new();
+    let values = |root: &Machine<u8>| {
+      let mut values: Vec<u8> = root.iter().map(|(_, value)| *value).collect();
+      values.sort();
+      values
+    };
+
+    assert!(root.is_empty());
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.len(), 3);
+    assert_eq!(values(&root), vec![1, 2, 3]);
+
+    root.finish().unwrap();
+    assert_eq!(values(&root), vec![1, 2, 3]);
+
+    // entries changed in the overlay are not listed twice
+    assert_eq!(root.domain_remove(&Vec::from(&b"example.org"[..])), RemoveResult::Ok(3));
+    assert_eq!(root.len(), 2);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 4), InsertResult::Ok);
+    assert_eq!(root.len(), 4);
+    assert_eq!(values(&root), vec![1, 2, 3, 4]);
+    assert_eq!(root.iter_under(&b"example.com"[..]).count(), 3);
+  }
 }
@@ -4,34 +4,60 @@
 //! in the wild).
 //! With this set of characters, we can encode keys on 6 bits, and hopefully get faster
 //! matching.
-//! It does not support wildcard matching yet.
+//! Since symbols straddle byte boundaries in the packed keys, the trie walks
+//! the keys symbol by symbol. This is how '*' and '.' are recognised for
+//! wildcard matching.
 
 use std::{iter,str};
 use std::fmt::Debug;
 use bitvec::{BitVec,BitSlice,BigEndian};
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, is_under, validate_key};
 
-pub fn ascii_to_6(c: u8) -> u8 {
+/// byte that has no 6 bits encoding, and its position in the encoded data
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct EncodeError {
+  pub byte:     u8,
+  pub position: usize,
+}
+
+pub fn ascii_to_6(c: u8) -> Option<u8> {
   //println!("will encode {} = {}", c, c as char);
   match c {
-    b'A'..=b'Z' => c - 0x41,
-    b'a'..=b'z' => c - 0x61,
-    b'0'..=b'9' => c - 0x30 + 26,
-    b'-'        => 37,
-    b'.'        => 38,
-    b'_'        => 39,
-    b'*'        => 40,
-    i           => panic!("could not convert character {} = {}", i, i as char)
+    b'A'..=b'Z' => Some(c - 0x41),
+    b'a'..=b'z' => Some(c - 0x61),
+    b'0'..=b'9' => Some(c - 0x30 + 26),
+    b'-'        => Some(37),
+    b'.'        => Some(38),
+    b'_'        => Some(39),
+    b'*'        => Some(40),
+    _           => None,
+  }
+}
+
+/// symbol used for '.'
+pub const DOT: u8 = 38;
+/// symbol used for '*'
+pub const STAR: u8 = 40;
+
+/// converts a 6 bits symbol back to ASCII (lowercase)
+pub fn six_to_ascii(c: u8) -> u8 {
+  match c {
+    0..=25  => c + 0x61,
+    26..=35 => c - 26 + 0x30,
+    37      => b'-',
+    38      => b'.',
+    39      => b'_',
+    40      => b'*',
+    _       => b'?',
   }
 }
 
-pub fn encode_6_bits(data: &[u8]) -> Vec<u8> {
+/// packs 6 bits symbols, the last byte is padded with zeros
+pub fn pack_6_bits<I: Iterator<Item=u8>>(symbols: I) -> Vec<u8> {
   let mut vec: BitVec<BigEndian> = BitVec::new();
-  for c in data.iter() {
-    let c2 = ascii_to_6(*c);
-    //println!("converting {} from {:b} to {:b}", c, c, c2);
-    let sl0: &[u8] = &[c2];
+  for c in symbols {
+    let sl0: &[u8] = &[c];
     let sl: &BitSlice = sl0.into();
     //println!("bitslice is {}", sl);
     vec.extend(sl.iter().skip(2).take(6));
@@ -42,78 +68,168 @@ pub fn encode_6_bits(data: &[u8]) -> Vec<u8> {
   v
 }
 
+pub fn encode_6_bits(data: &[u8]) -> Result<Vec<u8>, EncodeError> {
+  let mut symbols = Vec::with_capacity(data.len());
+  for (position, &byte) in data.iter().enumerate() {
+    match ascii_to_6(byte) {
+      Some(c) => symbols.push(c),
+      None    => return Err(EncodeError { byte, position }),
+    }
+  }
+
+  Ok(pack_6_bits(symbols.into_iter()))
+}
+
+/// reads the symbol at `index` in packed data
+pub fn symbol_at(data: &[u8], index: usize) -> u8 {
+  let bit = index * 6;
+  let byte = bit / 8;
+  let high = data[byte] as u16;
+  let low = data.get(byte + 1).cloned().unwrap_or(0) as u16;
+  (((high << 8 | low) >> (10 - bit % 8)) & 0x3f) as u8
+}
+
+/// a range of symbols in packed data. Symbols can straddle byte
+/// boundaries, so the trie compares them one by one instead of
+/// comparing bytes
+#[derive(Clone,Copy,Debug)]
+pub struct Symbols<'a> {
+  data:  &'a [u8],
+  start: usize,
+  end:   usize,
+}
+
+impl<'a> Symbols<'a> {
+  pub fn new(data: &'a [u8], len: usize) -> Symbols<'a> {
+    Symbols { data, start: 0, end: len }
+  }
+
+  pub fn len(&self) -> usize {
+    self.end - self.start
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.start == self.end
+  }
+
+  pub fn get(&self, index: usize) -> u8 {
+    symbol_at(self.data, self.start + index)
+  }
+
+  pub fn skip(&self, count: usize) -> Symbols<'a> {
+    Symbols { data: self.data, start: self.start + count, end: self.end }
+  }
+
+  pub fn take(&self, count: usize) -> Symbols<'a> {
+    Symbols { data: self.data, start: self.start, end: self.start + count }
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item=u8> + 'a {
+    let s = *self;
+    (0..s.len()).map(move |i| s.get(i))
+  }
+
+  /// position of the first symbol that differs, in the common length
+  pub fn mismatch(&self, other: &Symbols) -> Option<usize> {
+    self.iter().zip(other.iter()).position(|(a, b)| a != b)
+  }
+
+  pub fn contains(&self, symbol: u8) -> bool {
+    self.iter().any(|c| c == symbol)
+  }
+
+  /// packs the symbols in a new key
+  pub fn to_vec(&self) -> Key {
+    pack_6_bits(self.iter())
+  }
+}
+
 #[derive(Debug,PartialEq)]
 pub struct TrieNode<V> {
   key_value:  Option<KeyValue<Key,V>>,
   local_key:  Key,
+  local_len:  usize,
   child_keys: Vec<u8>,
   children:   Vec<TrieNode<V>>,
 }
 
 impl<V:Debug> TrieNode<V> {
-  pub fn new(key: Key, value: V) -> TrieNode<V> {
-    TrieNode {
+  /// fails if the key has characters outside of the 6 bits encoding
+  pub fn new(key: Key, value: V) -> Result<TrieNode<V>, InsertError> {
+    let local_key = encode_6_bits(&key).map_err(|_| InsertError::InvalidCharacter)?;
+
+    Ok(TrieNode {
       key_value:  Some((key.clone(), value)),
-      local_key:  key,
+      local_len:  key.len(),
+      local_key,
       child_keys: vec!(),
       children:   vec!(),
-    }
+    })
   }
 
   pub fn root() -> TrieNode<V> {
     TrieNode {
       key_value:  None,
       local_key:  vec!(),
+      local_len:  0,
       child_keys: vec!(),
       children:   vec!(),
     }
   }
 
+  fn local(&self) -> Symbols {
+    Symbols::new(&self.local_key, self.local_len)
+  }
+
   pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
+    let partial_key = match encode_6_bits(&key) {
+      Ok(partial_key) => partial_key,
+      Err(_) => return InsertResult::Failed(InsertError::InvalidCharacter),
+    };
+
     //handle the root
-    if self.local_key.is_empty() && self.child_keys.is_empty() {
-      self.local_key = key.clone();
+    if self.local_len == 0 && self.child_keys.is_empty() {
+      self.local_key = partial_key;
+      self.local_len = key.len();
       self.key_value = Some((key, value));
       return InsertResult::Ok;
     }
 
-    let res = self.insert_recursive(&key, &key, value);
-    assert_ne!(res, InsertResult::Failed);
+    let res = self.insert_recursive(Symbols::new(&partial_key, key.len()), &key, value);
     //println!("adding {}", str::from_utf8(&key).unwrap());
     res
   }
 
-  pub fn insert_recursive(&mut self, partial_key: &[u8], key: &Key, value: V) -> InsertResult {
-    assert_ne!(partial_key, &b""[..]);
-
-    /*println!("insert_recursive: partial_key={}, local_key={}",
-      str::from_utf8(partial_key).unwrap(),
-      str::from_utf8(&self.local_key).unwrap());
-    */
+  pub fn insert_recursive(&mut self, partial_key: Symbols, key: &Key, value: V) -> InsertResult {
+    if partial_key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
 
-    let pos = partial_key.iter().zip(self.local_key.iter()).position(|(&a,&b)| a != b);
+    let local_len = self.local_len;
+    let pos = partial_key.mismatch(&self.local());
     match pos {
       None => {
-        if partial_key.len() > self.local_key.len() {
-          //match self.child_keys.iter().position(|k| *k == partial_key[self.local_key.len()]) {
-          match self.child_keys.iter().position(|k| *k == partial_key[self.local_key.len()]) {
+        if partial_key.len() > local_len {
+          let rest = partial_key.skip(local_len);
+          match self.child_keys.iter().position(|k| *k == rest.get(0)) {
             None => {
               let new_child = TrieNode {
                 key_value:  Some((key.clone(), value)),
-                local_key:  partial_key[self.local_key.len()..].to_vec(),
+                local_key:  rest.to_vec(),
+                local_len:  rest.len(),
                 child_keys: vec!(),
                 children:   vec!(),
               };
-              self.child_keys.push(partial_key[self.local_key.len()]);
+              self.child_keys.push(rest.get(0));
               self.children.push(new_child);
 
               return InsertResult::Ok;
             }
             Some(index) => {
-              return self.children[index].insert_recursive(&partial_key[self.local_key.len()..], key, value);
+              return self.children[index].insert_recursive(rest, key, value);
             }
           }
-        } else if partial_key.len() == self.local_key.len() {
+        } else if partial_key.len() == local_len {
           if self.key_value.is_some() {
             return InsertResult::Existing;
           } else {
@@ -122,41 +238,54 @@ impl<V:Debug> TrieNode<V> {
         } else {
           //partial key is smaller, so insert the new value above
           //the current node
+          let (child_key, child_local_key) = {
+            let rest = self.local().skip(partial_key.len());
+            (rest.get(0), rest.to_vec())
+          };
           let new_child = TrieNode {
             key_value:  self.key_value.take(),
-            local_key:  self.local_key[partial_key.len()..].to_vec(),
-            child_keys: vec!(),
-            children:   vec!(),
+            local_key:  child_local_key,
+            local_len:  local_len - partial_key.len(),
+            child_keys: self.child_keys.drain(..).collect(),
+            children:   self.children.drain(..).collect(),
           };
 
           self.key_value =  Some((key.clone(), value));
-          self.child_keys.push(self.local_key[partial_key.len()]);
+          self.child_keys.push(child_key);
           self.children.push(new_child);
           self.local_key = partial_key.to_vec();
+          self.local_len = partial_key.len();
 
           return InsertResult::Ok;
 
         }
       },
       Some(index) => {
+        let (child_key, child_local_key, local_key) = {
+          let local = self.local();
+          (local.get(index), local.skip(index).to_vec(), local.take(index).to_vec())
+        };
         let new_child1 = TrieNode {
           key_value:  self.key_value.take(),
-          local_key:  self.local_key[index..].to_vec(),
+          local_key:  child_local_key,
+          local_len:  local_len - index,
           child_keys: self.child_keys.drain(..).collect(),
           children:   self.children.drain(..).collect(),
         };
         let new_child2 = TrieNode {
           key_value:  Some((key.clone(), value)),
-          local_key:  partial_key[index..].to_vec(),
+          local_key:  partial_key.skip(index).to_vec(),
+          local_len:  partial_key.len() - index,
           child_keys: vec!(),
           children:   vec!(),
         };
 
-        self.child_keys.push(self.local_key[index]);
+        self.child_keys.push(child_key);
         self.children.push(new_child1);
-        self.child_keys.push(partial_key[index]);
+        self.child_keys.push(partial_key.get(index));
         self.children.push(new_child2);
-        self.local_key.truncate(index);
+        self.local_key = local_key;
+        self.local_len = index;
         return InsertResult::Ok;
       }
     }
@@ -164,82 +293,71 @@ impl<V:Debug> TrieNode<V> {
     return InsertResult::Ok;
   }
 
-  pub fn remove(&mut self, partial_key: &Key) -> RemoveResult {
-    /*println!("remove: partial_key={}, local_key={}",
-      str::from_utf8(partial_key).unwrap(),
-      str::from_utf8(&self.local_key).unwrap());
-      */
+  /// removes the reversed key, and returns the entry
+  pub fn remove(&mut self, key: &Key) -> Option<KeyValue<Key,V>> {
+    let partial_key = match encode_6_bits(key) {
+      Ok(partial_key) => partial_key,
+      Err(_) => return None,
+    };
+    let partial_key = Symbols::new(&partial_key, key.len());
 
     //we check the lower level's local_key in remove_recursive,
     //so we handle the root node here
-    match partial_key.iter().zip(self.local_key.iter()).position(|(&a,&b)| a != b) {
+    match partial_key.mismatch(&self.local()) {
       None => {
-        let local_len = self.local_key.len();
+        let local_len = self.local_len;
         if partial_key.len() > local_len {
-          self.remove_recursive(&partial_key[local_len..])
+          self.remove_recursive(partial_key.skip(local_len))
         } else if partial_key.len() == local_len {
           if self.key_value.is_some() {
-            self.key_value = None;
+            let removed = self.key_value.take();
             if self.child_keys.is_empty() {
               self.local_key = vec!();
+              self.local_len = 0;
             }
 
-            RemoveResult::Ok
+            removed
           } else {
-            RemoveResult::NotFound
+            None
           }
         } else {
-          RemoveResult::NotFound
+          None
         }
       },
-      Some(_) => RemoveResult::NotFound
+      Some(_) => None
     }
 
   }
 
-  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult {
-    /*println!("remove_recursive: partial_key={}, local_key={}, child_keys={}",
-      str::from_utf8(partial_key).unwrap(),
-      str::from_utf8(&self.local_key).unwrap(),
-      str::from_utf8(&self.child_keys).unwrap());
-    */
-
-    assert_ne!(partial_key, &b""[..]);
-    match self.child_keys.iter().position(|k| *k == partial_key[0]) {
-      None => RemoveResult::NotFound,
+  pub fn remove_recursive(&mut self, partial_key: Symbols) -> Option<KeyValue<Key,V>> {
+    assert!(!partial_key.is_empty());
+    match self.child_keys.iter().position(|k| *k == partial_key.get(0)) {
+      None => None,
       Some(index) => {
         let res = {
           let child = &mut self.children[index];
-          match partial_key.iter().zip(child.local_key.iter()).position(|(&a,&b)| a != b) {
+          match partial_key.mismatch(&child.local()) {
             None => {
-              let child_local_len = child.local_key.len();
+              let child_local_len = child.local_len;
               if partial_key.len() > child_local_len {
-                child.remove_recursive(&partial_key[child_local_len..])
+                child.remove_recursive(partial_key.skip(child_local_len))
               } else if partial_key.len() == child_local_len {
                 if child.key_value.is_some() {
         //println!("removing key_value: {:?}", child.key_value);
-                  child.key_value = None;
-                  RemoveResult::Ok
+                  child.key_value.take()
                 } else {
-                  RemoveResult::NotFound
+                  None
                 }
               } else {
-                RemoveResult::NotFound
+                None
               }
             },
-            Some(_) => RemoveResult::NotFound
+            Some(_) => None
           }
         };
 
         // we might have some cleanup to do
-        if res == RemoveResult::Ok {
-          /*println!("will cleanup. child has key_value:{} child has child_keys:{}",
-          self.children[index].key_value.is_some(),
-          self.children[index].child_keys.len());
-          self.print();
-          */
-
-
+        if res.is_some() {
           if self.children[index].key_value.is_none() && self.children[index].child_keys.is_empty() {
             self.child_keys.remove(index);
             self.children.remove(index);
@@ -247,8 +365,9 @@ impl<V:Debug> TrieNode<V> {
 
           if self.child_keys.len() == 1 {
             match (self.key_value.is_some(), self.children[0].key_value.is_some()) {
-              // we keep the child because both current node and child have values
-              (true, true) => return res,
+              // we keep the child because the current node has a value: merging
+              // it would move that value to a longer key
+              (true, _) => return res,
               // we take the child's value
               (false, true) => {
                 self.key_value = self.children[0].key_value.take();
@@ -257,22 +376,14 @@ impl<V:Debug> TrieNode<V> {
             }
 
             let _k = self.child_keys.remove(0);
-            let mut child = self.children.remove(0);
-            self.local_key.extend(child.local_key.drain(..));
+            let child = self.children.remove(0);
+            self.local_key = pack_6_bits(self.local().iter().chain(child.local().iter()));
+            self.local_len += child.local_len;
             if !child.child_keys.is_empty() {
               self.child_keys = child.child_keys;
               self.children = child.children;
             }
           }
-
-        /*
-        println!("remove_recursive: after cleanup partial_key={}, local_key={}, child_keys={}",
-      str::from_utf8(partial_key).unwrap(),
-      str::from_utf8(&self.local_key).unwrap(),
-      str::from_utf8(&self.child_keys).unwrap());
-          self.print();
-          */
-
         }
 
         res
@@ -281,26 +392,31 @@ impl<V:Debug> TrieNode<V> {
   }
 
   // specific version that will handle wildcard domains
-  pub fn domain_lookup_recursive(&self, partial_key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    assert_ne!(partial_key, &b""[..]);
-
-    /*println!("domain_lookup_recursive: partial_key={}, local_key={}, child_keys={}",
-      str::from_utf8(partial_key).unwrap(),
-      str::from_utf8(&self.local_key).unwrap(),
-      str::from_utf8(&self.child_keys).unwrap(),
-    );
-    */
-    let pos = partial_key.iter().zip(self.local_key.iter()).position(|(&a,&b)| a != b);
+  pub fn domain_lookup_recursive(&self, partial_key: Symbols) -> Option<&KeyValue<Key,V>> {
+    // an empty host cannot match any key
+    if partial_key.is_empty() {
+      return None;
+    }
+
+    let local_len = self.local_len;
+    let pos = partial_key.mismatch(&self.local());
     match pos {
       None => {
-        let local_len = self.local_key.len();
         if partial_key.len() > local_len {
-          match self.child_keys.iter().position(|k| *k == partial_key[local_len]) {
+          let rest = partial_key.skip(local_len);
+          let res = match self.child_keys.iter().position(|k| *k == rest.get(0)) {
             None => None,
             Some(index) => {
-              self.children[index].domain_lookup_recursive(&partial_key[local_len..])
+              self.children[index].domain_lookup_recursive(rest)
             }
-          }
+          };
+
+          // no exact match, a wildcard child can still match this label
+          res.or_else(|| {
+            self.child_keys.iter().position(|k| *k == STAR).and_then(|index| {
+              self.children[index].domain_lookup_recursive(rest)
+            })
+          })
         } else if partial_key.len() == local_len {
           self.key_value.as_ref()
         } else {
@@ -309,9 +425,8 @@ impl<V:Debug> TrieNode<V> {
       },
       Some(i) => {
         // check for wildcard
-        if i+1 == self.local_key.len() && self.local_key[i] == '*' as u8 {
-          let c = '.' as u8;
-          if (&partial_key[i..]).contains(&c) {
+        if i+1 == local_len && self.local().get(i) == STAR {
+          if partial_key.skip(i).contains(DOT) {
             None
           } else {
             self.key_value.as_ref()
@@ -324,22 +439,68 @@ impl<V:Debug> TrieNode<V> {
     }
   }
 
+  // exact version of `domain_lookup_recursive`
+  fn get_mut_recursive(&mut self, partial_key: Symbols) -> Option<&mut V> {
+    let local_len = self.local_len;
+    if partial_key.len() < local_len || partial_key.mismatch(&self.local()).is_some() {
+      return None;
+    }
+
+    if partial_key.len() == local_len {
+      return self.key_value.as_mut().map(|kv| &mut kv.1);
+    }
+
+    let rest = partial_key.skip(local_len);
+    let index = self.child_keys.iter().position(|k| *k == rest.get(0))?;
+    self.children[index].get_mut_recursive(rest)
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains. The keys are packed in 6 bit
+  /// symbols, so this filters every entry instead of walking the subtree
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.children.iter().fold(self.key_value.iter().count(), |acc, c| acc + c.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.iter().all(|c| c.is_empty())
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.iter() {
+      child.collect(entries);
+    }
+  }
+
   pub fn print(&self) {
-    //self.print_recursive(b'.', 0)
+    //self.print_recursive(DOT, 0)
   }
 
   pub fn print_recursive(&self, partial_key: u8, indent:u8) {
     let raw_prefix:Vec<u8> = iter::repeat(' ' as u8).take(2*indent as usize).collect();
     let prefix = str::from_utf8(&raw_prefix).unwrap();
-    let c: char = partial_key.into();
+    let c: char = six_to_ascii(partial_key).into();
+    let local_key: String = self.local().iter().map(|s| six_to_ascii(s) as char).collect();
 
     if let Some((ref key, ref value)) = self.key_value {
-    println!("{}{}: {}|({},{:?})", prefix, c,
-      str::from_utf8(&self.local_key).unwrap(),
+    println!("{}{}: {}|({},{:?})", prefix, c, local_key,
       str::from_utf8(&key).unwrap(), value);
     } else {
-    println!("{}{}: {}|None", prefix, c,
-      str::from_utf8(&self.local_key).unwrap());
+    println!("{}{}: {}|None", prefix, c, local_key);
     }
     for (child_key, ref child) in self.child_keys.iter().zip(self.children.iter()) {
       child.print_recursive(*child_key, indent+1);
@@ -349,35 +510,51 @@ impl<V:Debug> TrieNode<V> {
 
 impl<V: Debug> DomainLookup<V> for TrieNode<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     let mut partial_key = key.clone();
     partial_key.reverse();
-    let partial_key = encode_6_bits(&partial_key);
+    let partial_key = match encode_6_bits(&partial_key) {
+      Ok(partial_key) => partial_key,
+      Err(_) => return InsertResult::Failed(InsertError::InvalidCharacter),
+    };
 
     //handle the root
-    if self.local_key.is_empty() && self.child_keys.is_empty() {
+    if self.local_len == 0 && self.child_keys.is_empty() {
       self.local_key = partial_key;
+      self.local_len = key.len();
       self.key_value = Some((key, value));
       return InsertResult::Ok;
     }
 
-    self.insert_recursive(&partial_key, &key, value)
+    self.insert_recursive(Symbols::new(&partial_key, key.len()), &key, value)
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
     let mut partial_key = key.clone();
     partial_key.reverse();
-    let partial_key = encode_6_bits(&partial_key);
-    self.remove(&partial_key)
+    self.remove(&partial_key).into()
   }
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
     let mut partial_key = key.to_vec();
     partial_key.reverse();
-    let partial_key = encode_6_bits(&partial_key);
-    self.domain_lookup_recursive(&partial_key)
+    // hosts that cannot be encoded cannot match any key
+    let partial_key = encode_6_bits(&partial_key).ok()?;
+    self.domain_lookup_recursive(Symbols::new(&partial_key, key.len()))
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let mut partial_key = key.to_vec();
+    partial_key.reverse();
+    let partial_key = encode_6_bits(&partial_key).ok()?;
+    self.get_mut_recursive(Symbols::new(&partial_key, key.len()))
   }
 }
 
@@ -387,10 +564,18 @@ mod tests {
 
   #[test]
   fn encode() {
-    let _test = encode_6_bits(&b"abcd"[..]);
-    let _test = encode_6_bits(&b"efgh"[..]);
-    let _test = encode_6_bits(&b"abcd.efgh"[..]);
+    let _test = encode_6_bits(&b"abcd"[..]).unwrap();
+    let _test = encode_6_bits(&b"efgh"[..]).unwrap();
+    let _test = encode_6_bits(&b"abcd.efgh"[..]).unwrap();
     //panic!();
+
+    let test = encode_6_bits(&b"gro.*"[..]).unwrap();
+    let symbols = Symbols::new(&test, 5);
+    let decoded: Vec<u8> = symbols.iter().map(six_to_ascii).collect();
+    assert_eq!(&decoded[..], &b"gro.*"[..]);
+    assert_eq!(symbols.get(3), DOT);
+    assert_eq!(symbols.get(4), STAR);
+    assert_eq!(symbols.skip(3).to_vec(), encode_6_bits(&b".*"[..]).unwrap());
   }
 
   #[test]
@@ -432,7 +617,8 @@ mod tests {
 
     println!("before remove");
     root.print();
-    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), Some((Vec::from(&b"abce"[..]), 2)));
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), None);
     println!("after remove");
     root.print();
 
@@ -440,7 +626,7 @@ mod tests {
     root2.print();
     assert_eq!(root, root2);
 
-    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), Some((Vec::from(&b"abgh"[..]), 3)));
     println!("after remove");
     root.print();
     println!("expected");
@@ -476,7 +662,7 @@ mod tests {
 
     println!("root2:");
     root2.print();
-    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok);
+    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), Some((Vec::from(&b"abc"[..]), 3)));
 
     println!("root2 after,remove:");
     root2.print();
@@ -512,16 +698,83 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
     assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&((&b"alldomains.org"[..]).to_vec(), 4)));
-    //assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
-    //assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
-    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
     println!("after remove");
     root.print();
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
-    //assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
-    //assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
   }
+
+  #[test]
+  fn remove_keeps_parent_value() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    // `example.com` keeps one child without a value, `.`, once
+    // `xexample.com` is removed: it must not be merged into the node
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"xexample.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.a.example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.b.example.com"[..]), 4), InsertResult::Ok);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"xexample.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_lookup(&b"xexample.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), Some(&((&b"example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"www.a.example.com"[..]), Some(&((&b"www.a.example.com"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"www.b.example.com"[..]), Some(&((&b"www.b.example.com"[..]).to_vec(), 4)));
+  }
+
+  #[test]
+  fn wildcard() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.alldomains.org"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.alldomains.org"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.a.org"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.ab.org"[..]), 4), InsertResult::Ok);
+
+    assert_eq!(root.domain_lookup(&b"www.alldomains.org"[..]), Some(&((&b"www.alldomains.org"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"wwx.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"test.a.org"[..]), Some(&((&b"*.a.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"test.ab.org"[..]), Some(&((&b"*.ab.org"[..]).to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"blah.test.a.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"a.org"[..]), None);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"*.alldomains.org"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.alldomains.org"[..]), Some(&((&b"www.alldomains.org"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.ab.org"[..]), Some(&((&b"*.ab.org"[..]).to_vec(), 4)));
+  }
+
+  #[test]
+  fn invalid_characters() {
+    assert_eq!(encode_6_bits(&b"www.example.com"[..]).is_ok(), true);
+    assert_eq!(encode_6_bits(&b"www example.com"[..]), Err(EncodeError { byte: b' ', position: 3 }));
+    assert_eq!(encode_6_bits(&b"www.ex%41mple.com"[..]), Err(EncodeError { byte: b'%', position: 6 }));
+    assert_eq!(encode_6_bits(&b"\x00\xff"[..]), Err(EncodeError { byte: 0, position: 0 }));
+    assert_eq!(encode_6_bits("www.exämple.com".as_bytes()), Err(EncodeError { byte: 0xc3, position: 6 }));
+
+    let mut root: TrieNode<u8> = TrieNode::root();
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www example.com"[..]), 3), InsertResult::Failed(InsertError::InvalidCharacter));
+    assert_eq!(root.domain_insert("exämple.com".as_bytes().to_vec(), 4), InsertResult::Failed(InsertError::InvalidCharacter));
+
+    assert_eq!(root.domain_lookup(&b"www example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www%2eexample.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"\x00\x01\xff.example.com"[..]), None);
+    assert_eq!(root.domain_lookup("ünicode.example.com".as_bytes()), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_remove(&Vec::from(&b"www example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_lookup(&b""[..]), None);
+
+    assert_eq!(TrieNode::new(Vec::from(&b"www example.com"[..]), 5u8).err(), Some(InsertError::InvalidCharacter));
+    assert!(TrieNode::new(Vec::from(&b"www.example.com"[..]), 5u8).is_ok());
+  }
 }
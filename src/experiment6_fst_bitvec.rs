@@ -2,42 +2,20 @@
 //!
 //! this example uses a state machine that must be completely regenerated
 //! on each change, and keys must be inserted in order
+//!
+//! keys are sorted on their 6 bits encoding, since it does not keep the
+//! ASCII order. Keys that encode to the same bytes, like different cases
+//! of the same domain, are reported as duplicates by `finish`. Keys with
+//! characters outside of the encoding are rejected by `domain_insert`
+//!
+//! wildcards are matched by looking up the host again, with its first label
+//! replaced by '*' before encoding
 
-use std::fmt::{Display};
 use fst::{MapBuilder,Map};
-use bitvec::*;
-
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
-
-pub fn ascii_to_6(c: u8) -> u8 {
-  //println!("will encode {} = {}", c, c as char);
-  match c {
-    b'A'..=b'Z' => c - 0x41,
-    b'a'..=b'z' => c - 0x61,
-    b'0'..=b'9' => c - 0x30 + 26,
-    b'-'        => 37,
-    b'.'        => 38,
-    b'_'        => 39,
-    b'*'        => 40,
-    i           => panic!("could not convert character {} = {}", i, i as char)
-  }
-}
 
-pub fn encode_6_bits(data: &[u8]) -> Vec<u8> {
-  let mut vec: BitVec<BigEndian> = BitVec::new();
-  for c in data.iter() {
-    let c2 = ascii_to_6(*c);
-    //println!("converting {} from {:b} to {:b}", c, c, c2);
-    let sl0: &[u8] = &[c2];
-    let sl: &BitSlice = sl0.into();
-    //println!("bitslice is {}", sl);
-    vec.extend(sl.iter().skip(2).take(6));
-    //println!("bitvec is now {}", vec);
-  }
-  let v = vec.into();
-  //println!("result: {:x?}", v);
-  v
-}
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, is_under, validate_key};
+pub use super::experiment4_fst::{FinishReport, BuildError};
+pub use super::experiment5_trie_bitvec::{ascii_to_6, encode_6_bits, EncodeError};
 
 pub struct Machine<V> {
   index: Vec<KeyValue<Key,V>>,
@@ -49,7 +27,7 @@ pub enum MachineMap<V> {
   Map(Map),
 }
 
-impl<V: Ord+Display> Machine<V> {
+impl<V> Machine<V> {
   pub fn new() -> Self {
     Machine {
       index: Vec::new(),
@@ -57,42 +35,59 @@ impl<V: Ord+Display> Machine<V> {
     }
   }
 
-  pub fn finish(&mut self) {
+  pub fn finish(&mut self) -> Result<FinishReport, BuildError> {
     let mut builder = MapBuilder::memory();
+    let mut skipped = Vec::new();
 
     match self.map {
-      MachineMap::Map(_) => panic!("already finished"),
+      MachineMap::Map(_) => return Err(BuildError::AlreadyFinished),
       MachineMap::Building(ref mut v) => {
-        v.sort_by(|a, b| a.0.iter().rev().cmp(b.0.iter().rev()));
-
-        let mut index = 0u64;
+        let mut encoded: Vec<(Vec<u8>, (Key, V))> = Vec::with_capacity(v.len());
         for (k, v) in v.drain(..) {
           let mut key = k.to_vec();
           key.reverse();
-          let key = encode_6_bits(&key);
+          match encode_6_bits(&key) {
+            Ok(key) => encoded.push((key, (k, v))),
+            Err(_) => skipped.push((k, InsertResult::Failed(InsertError::InvalidCharacter))),
+          }
+        }
+        // stable sort, so the first inserted of duplicate keys is kept
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
 
-          if let Err(_e) = builder.insert(&key, index) {
+        let mut index = 0u64;
+        let mut previous: Option<Vec<u8>> = None;
+        for (key, (k, v)) in encoded.drain(..) {
+          if previous.as_ref() == Some(&key) {
+            skipped.push((k, InsertResult::Existing));
+          } else if let Err(_e) = builder.insert(&key, index) {
             //println!("error inserting key: {:?}", e);
+            skipped.push((k, InsertResult::Failed(InsertError::Conflict)));
           } else {
             //println!("inserted {} -> ({}, {})", std::str::from_utf8(&k).unwrap(),
             //  std::str::from_utf8(&key).unwrap(), v);
             self.index.push((k, v));
             index += 1;
+            previous = Some(key);
           }
         }
       }
     }
 
     //println!("{} bytes written to stream", builder.bytes_written());
-    let v = builder.into_inner().unwrap();
+    let v = builder.into_inner()?;
+
+    self.map = MachineMap::Map(Map::from_bytes(v)?);
 
-    self.map = MachineMap::Map(Map::from_bytes(v).unwrap());
+    Ok(FinishReport {
+      inserted: self.index.len(),
+      skipped,
+    })
   }
 
   pub fn lookup(&self, key: &[u8]) -> Option<u64> {
     let mut partial_key = key.to_vec();
     partial_key.reverse();
-    let partial_key = encode_6_bits(&partial_key);
+    let partial_key = encode_6_bits(&partial_key).ok()?;
 
     match self.map {
       MachineMap::Map(ref m) => {
@@ -103,16 +98,50 @@ impl<V: Ord+Display> Machine<V> {
       }
     }
   }
+
+  /// every entry, either waiting for `finish` or in the fst
+  pub fn iter(&self) -> Iter<V> {
+    Iter::new(self.entries())
+  }
+
+  /// entries for the domain and its subdomains, found by filtering every entry
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = self.entries();
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    match self.map {
+      MachineMap::Building(ref v) => v.len(),
+      MachineMap::Map(_) => self.index.len(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  fn entries(&self) -> Vec<&KeyValue<Key,V>> {
+    match self.map {
+      MachineMap::Building(ref v) => v.iter().collect(),
+      MachineMap::Map(_) => self.index.iter().collect(),
+    }
+  }
 }
 
 impl<V> DomainLookup<V> for Machine<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
     //let mut k = key.to_vec();
     //k.reverse();
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
 
     match self.map {
-      MachineMap::Map(_) => panic!("already finished"),
+      MachineMap::Map(_) => return InsertResult::Failed(InsertError::AlreadyFinished),
       MachineMap::Building(ref mut v) => {
         v.push((key, value));
       }
@@ -121,28 +150,62 @@ impl<V> DomainLookup<V> for Machine<V> {
     InsertResult::Ok
   }
 
-  // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, _key: &Key) -> RemoveResult {
-    unimplemented!()
+  /// removes a key waiting for `finish`. The fst cannot be changed once
+  /// built, so removing returns `Unsupported` after `finish`
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let key = key.to_ascii_lowercase();
+    match self.map {
+      MachineMap::Map(_) => RemoveResult::Unsupported,
+      MachineMap::Building(ref mut v) => {
+        match v.iter().position(|kv| kv.0 == key) {
+          Some(index) => Some(v.remove(index)).into(),
+          None => RemoveResult::NotFound,
+        }
+      }
+    }
   }
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
     let mut partial_key = key.to_vec();
     partial_key.reverse();
-    let partial_key = encode_6_bits(&partial_key);
 
     //println!("looking up {} -> {}", std::str::from_utf8(key).unwrap(), std::str::from_utf8(&partial_key).unwrap());
 
     match self.map {
       MachineMap::Map(ref m) => {
-        m.get(partial_key).and_then(|i| self.index.get(i as usize))
+        // hosts that cannot be encoded cannot match any key
+        let encoded = encode_6_bits(&partial_key).ok()?;
+        let res = m.get(encoded).or_else(|| {
+          // wildcards only match one label: look up the reversed host with
+          // its last label replaced by '*'. The key is encoded again, since
+          // the '*' symbol does not start on a byte boundary
+          match partial_key.iter().rposition(|&c| c == b'.') {
+            Some(pos) if pos + 1 < partial_key.len() => {
+              partial_key.truncate(pos + 1);
+              partial_key.push(b'*');
+              encode_6_bits(&partial_key).ok().and_then(|k| m.get(k))
+            },
+            _ => None,
+          }
+        });
+        res.and_then(|i| self.index.get(i as usize))
       },
       MachineMap::Building(_) => {
         panic!("builder not finished");
       }
     }
   }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let key = key.to_ascii_lowercase();
+    let index = match self.map {
+      MachineMap::Building(ref mut v) => return v.iter_mut().find(|kv| kv.0 == key).map(|kv| &mut kv.1),
+      MachineMap::Map(_) => self.lookup(&key)?,
+    };
+
+    self.index.get_mut(index as usize).map(|kv| &mut kv.1)
+  }
 }
 
 #[cfg(test)]
@@ -158,7 +221,7 @@ mod tests {
     assert_eq!(root.insert(Vec::from(&b"abce"[..]), 2), InsertResult::Ok);
     assert_eq!(root.insert(Vec::from(&b"abgh"[..]), 3), InsertResult::Ok);
 
-    root.finish();
+    root.finish().unwrap();
     //assert_eq!(root.lookup(&b"abce"[..]), Some(&((&b"abce"[..]).to_vec(), 2)));
     //assert!(false);
   }
@@ -257,16 +320,120 @@ mod tests {
     assert_eq!(root.domain_insert(Vec::from(&b"alldomains.org"[..]), 4), InsertResult::Ok);
     assert_eq!(root.domain_insert(Vec::from(&b"hello.com"[..]), 5), InsertResult::Ok);
 
-    root.finish();
+    root.finish().unwrap();
 
     assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
     assert_eq!(root.domain_lookup(&b"blah.test.example.com"[..]), None);
 
     assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), Some(&((&b"alldomains.org"[..]).to_vec(), 4)));
-    //assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
-    //assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"test.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
   }
+
+  #[test]
+  fn duplicates() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"WWW.example.com"[..]), 3), InsertResult::Ok);
+
+    let report = root.finish().unwrap();
+    assert_eq!(report, FinishReport {
+      inserted: 2,
+      skipped: vec![(Vec::from(&b"www.example.com"[..]), InsertResult::Existing)],
+    });
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&((&b"test.example.com"[..]).to_vec(), 2)));
+  }
+
+  #[test]
+  fn invalid_characters() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www example.com"[..]), 3), InsertResult::Failed(InsertError::InvalidCharacter));
+    assert_eq!(root.domain_insert(Vec::from(&b"www..example.com"[..]), 3), InsertResult::Failed(InsertError::InvalidPattern));
+
+    let report = root.finish().unwrap();
+    assert_eq!(report, FinishReport {
+      inserted: 2,
+      skipped: vec![],
+    });
+
+    assert_eq!(root.domain_lookup(&b"www example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www%2eexample.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"\x00\x01\xff.example.com"[..]), None);
+    assert_eq!(root.domain_lookup("ünicode.example.com".as_bytes()), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&((&b"*.example.com"[..]).to_vec(), 2)));
+  }
+
+  #[test]
+  fn finished() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"TEST.example.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_remove(&Vec::from(&b"test.example.com"[..])), RemoveResult::NotFound);
+
+    assert_eq!(root.finish().unwrap().inserted, 1);
+
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Failed(InsertError::AlreadyFinished));
+    assert_eq!(root.domain_remove(&Vec::from(&b"www.example.com"[..])), RemoveResult::Unsupported);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), None);
+  }
+
+  #[test]
+  fn mixed_case() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.EXAMPLE.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.AllDomains.org"[..]), 2), InsertResult::Ok);
+    root.finish().unwrap();
+
+    assert_eq!(root.domain_lookup(&b"WwW.eXaMpLe.CoM"[..]), Some(&(b"www.example.com".to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"TEST.ALLDOMAINS.ORG"[..]), Some(&(b"*.alldomains.org".to_vec(), 2)));
+  }
+
+  #[test]
+  fn upsert() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 1), Ok(None));
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 2), Ok(Some(1)));
+    root.finish().unwrap();
+
+    // the values change in place, but there is no new key after finish
+    assert_eq!(root.domain_upsert(Vec::from(&b"test.example.com"[..]), 3), Err(InsertError::AlreadyFinished));
+    *root.get_mut(&b"www.example.com"[..]).unwrap() = 3;
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(3));
+  }
+
+  #[test]
+  fn iter() {
+    let mut root: Machine<u8> = Machine::new();
+    let values = |root: &Machine<u8>| {
+      let mut values: Vec<u8> = root.iter().map(|(_, value)| *value).collect();
+      values.sort();
+      values
+    };
+
+    assert!(root.is_empty());
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 3), InsertResult::Ok);
+    assert_eq!(values(&root), vec![1, 2, 3]);
+
+    root.finish().unwrap();
+    assert_eq!(root.len(), 3);
+    assert_eq!(values(&root), vec![1, 2, 3]);
+    assert_eq!(root.iter_under(&b"example.com"[..]).count(), 2);
+  }
 }
@@ -4,12 +4,12 @@
 //! on each change, and keys must be inserted in order
 
 use std::fmt::{Display};
-use regex::bytes::{RegexSet,RegexSetBuilder};
+use regex::bytes::{Regex,RegexSet,RegexSetBuilder};
 use uuid::Uuid;
 use rand::{XorShiftRng, Rng};
 use std::collections::HashSet;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, sort_by_specificity, is_under};
 
 pub fn gen_uuid_seed_domain(top_level_domain: &str) -> Vec<u8> {
     let sub_domain_uuid = Uuid::new_v4().simple().to_string();
@@ -113,6 +113,7 @@ impl<V: Ord+Display> Machine<V> {
     }
 
     set.size_limit(1048576 *200);
+    set.case_insensitive(true);
     self.map = RegexMap::Map(set.build().unwrap());
   }
 
@@ -129,6 +130,51 @@ impl<V: Ord+Display> Machine<V> {
       }
     }
   }
+
+  /// every entry, either waiting for `finish` or in the regex set
+  pub fn iter(&self) -> Iter<V> {
+    Iter::new(self.entries())
+  }
+
+  /// entries for the domain and its subdomains, found by filtering every entry
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = self.entries();
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    match self.map {
+      RegexMap::Building(ref v) => v.len(),
+      RegexMap::Map(_) => self.index.len(),
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  fn entries(&self) -> Vec<&KeyValue<Key,V>> {
+    match self.map {
+      RegexMap::Building(ref v) => v.iter().collect(),
+      RegexMap::Map(_) => self.index.iter().collect(),
+    }
+  }
+}
+
+/// keys are regexes written backwards, like `$www.example.com^`. They are
+/// reversed byte by byte, so they must be ASCII and parse once reversed
+fn validate_pattern(key: &[u8]) -> Result<(), InsertError> {
+  if key.is_empty() {
+    return Err(InsertError::EmptyKey);
+  }
+
+  if !key.is_ascii() {
+    return Err(InsertError::InvalidCharacter);
+  }
+
+  let pattern: String = key.iter().rev().map(|&c| c as char).collect();
+  Regex::new(&pattern).map(|_| ()).map_err(|_| InsertError::InvalidPattern)
 }
 
 impl<V> DomainLookup<V> for Machine<V> {
@@ -136,9 +182,14 @@ impl<V> DomainLookup<V> for Machine<V> {
   fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
     //let mut k = key.to_vec();
     //k.reverse();
+    // the set is case insensitive, and lowercasing would change the meaning
+    // of escapes like `\D` or `\W`
+    if let Err(e) = validate_pattern(&key) {
+      return InsertResult::Failed(e);
+    }
 
     match self.map {
-      RegexMap::Map(_) => panic!("already finished"),
+      RegexMap::Map(_) => return InsertResult::Failed(InsertError::AlreadyFinished),
       RegexMap::Building(ref mut v) => {
         v.push((key, value));
       }
@@ -147,9 +198,18 @@ impl<V> DomainLookup<V> for Machine<V> {
     InsertResult::Ok
   }
 
-  // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, _key: &Key) -> RemoveResult {
-    unimplemented!()
+  /// removes a pattern waiting for `finish`. The set cannot be changed once
+  /// built, so removing returns `Unsupported` after `finish`
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    match self.map {
+      RegexMap::Map(_) => RemoveResult::Unsupported,
+      RegexMap::Building(ref mut v) => {
+        match v.iter().position(|kv| &kv.0 == key) {
+          Some(index) => Some(v.remove(index)).into(),
+          None => RemoveResult::NotFound,
+        }
+      }
+    }
   }
 
   // specific version that will handle wildcard domains
@@ -171,6 +231,35 @@ impl<V> DomainLookup<V> for Machine<V> {
       }
     }
   }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    // keys are patterns, so this compares them instead of matching them
+    let entries = match self.map {
+      RegexMap::Building(ref mut v) => v,
+      RegexMap::Map(_) => &mut self.index,
+    };
+
+    entries.iter_mut().find(|kv| kv.0.eq_ignore_ascii_case(key)).map(|kv| &mut kv.1)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut partial_key = key.to_vec();
+    partial_key.reverse();
+
+    match self.map {
+      RegexMap::Map(ref m) => {
+        // the set already tests every regex, we only need to keep all of the results
+        let mut matches: Vec<&KeyValue<Key,V>> = m.matches(&partial_key).into_iter()
+          .filter_map(|i| self.index.get(i))
+          .collect();
+        sort_by_specificity(&mut matches);
+        matches
+      },
+      RegexMap::Building(_) => {
+        panic!("builder not finished");
+      }
+    }
+  }
 }
 
 #[cfg(test)]
@@ -275,6 +364,113 @@ mod tests {
   }
   */
 
+  #[test]
+  fn regex_case() {
+    let mut root: Machine<u8> = Machine::new();
+
+    // `^moc.elpmaxe.\D+.ndc$` once reversed
+    assert_eq!(root.domain_insert(Vec::from(&b"$CDN.+D\\.Example.com^"[..]), 1), InsertResult::Ok);
+    root.finish();
+
+    assert_eq!(root.domain_lookup(&b"cdn.abc.example.com"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"CDN.abc.EXAMPLE.com"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"cdn.123.example.com"[..]), None);
+  }
+
+  #[test]
+  fn mixed_case() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"$Washtucna.obeliskoide.org^"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"$www.EXAMPLE.com^"[..]), 2), InsertResult::Ok);
+    root.finish();
+
+    assert_eq!(root.domain_lookup(&b"WASHTUCNA.OBELISKOIDE.ORG"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"Washtucna.Obeliskoide.Org"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"WwW.eXaMpLe.CoM"[..]).map(|kv| kv.1), Some(2));
+    assert_eq!(root.domain_lookup(&b"www.example.net"[..]), None);
+  }
+
+  #[test]
+  fn lookup_all() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"$*.example.com^"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"$www.example.com^"[..]), 2), InsertResult::Ok);
+    root.finish();
+
+    let values = |host: &[u8]| root.domain_lookup_all(host).iter().map(|kv| kv.1).collect::<Vec<u8>>();
+    assert_eq!(values(&b"www.example.com"[..]), vec![2, 1]);
+    assert_eq!(values(&b"test.example.com"[..]), vec![1]);
+    assert_eq!(values(&b"www.example.org"[..]), Vec::<u8>::new());
+  }
+
+  #[test]
+  fn upsert() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_upsert(Vec::from(&b"$www.example.com^"[..]), 1), Ok(None));
+    assert_eq!(root.domain_upsert(Vec::from(&b"$www.example.com^"[..]), 2), Ok(Some(1)));
+    root.finish();
+
+    *root.get_mut(&b"$www.example.com^"[..]).unwrap() = 3;
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(3));
+  }
+
+  #[test]
+  fn invalid_keys() {
+    let mut root: Machine<u8> = Machine::new();
+
+    // keys are reversed regexes
+    assert_eq!(root.domain_insert(vec![], 1), InsertResult::Failed(InsertError::EmptyKey));
+    assert_eq!(root.domain_insert(Vec::from(&b"$(www.example.com^"[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert("$www.ex\u{e4}mple.com^".as_bytes().to_vec(), 1), InsertResult::Failed(InsertError::InvalidCharacter));
+    assert_eq!(root.domain_insert(Vec::from(&b"$www.example.com^"[..]), 2), InsertResult::Ok);
+    root.finish();
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(2));
+  }
+
+  #[test]
+  fn iter() {
+    let mut root: Machine<u8> = Machine::new();
+    let values = |root: &Machine<u8>| {
+      let mut values: Vec<u8> = root.iter().map(|(_, value)| *value).collect();
+      values.sort();
+      values
+    };
+
+    assert!(root.is_empty());
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 3), InsertResult::Ok);
+
+    // listed before and after finish
+    assert_eq!(root.len(), 3);
+    assert_eq!(values(&root), vec![1, 2, 3]);
+    root.finish();
+    assert_eq!(root.len(), 3);
+    assert_eq!(values(&root), vec![1, 2, 3]);
+    assert_eq!(root.iter_under(&b"example.com"[..]).count(), 2);
+  }
+
+  #[test]
+  fn finished() {
+    let mut root: Machine<u8> = Machine::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"$www.example.com^"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"$test.example.com^"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"$test.example.com^"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_remove(&Vec::from(&b"$test.example.com^"[..])), RemoveResult::NotFound);
+
+    root.finish();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"$test.example.com^"[..]), 2), InsertResult::Failed(InsertError::AlreadyFinished));
+    assert_eq!(root.domain_remove(&Vec::from(&b"$www.example.com^"[..])), RemoveResult::Unsupported);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), None);
+  }
+
   #[test]
   fn domains() {
     let mut root: Machine<u8> = Machine::new();
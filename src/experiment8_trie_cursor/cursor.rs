@@ -42,6 +42,23 @@ impl<'a> HttpCursor<'a> {
     }
   }
 
+  /// length of the host part that was not consumed yet, 0 once we are in the URI
+  pub fn host_len(&self) -> usize {
+    match self.position {
+      Some(Position::HostUri(ref h, _)) => h.len(),
+      _ => 0,
+    }
+  }
+
+  /// true if the rest of the host ends with a '.', so what was consumed is a
+  /// parent domain of the host
+  pub fn at_label_boundary(&self) -> bool {
+    match self.position {
+      Some(Position::HostUri(ref h, _)) => !h.at_end() && h.next_char() == b'.',
+      _ => false,
+    }
+  }
+
   pub fn current_slice(&self) -> &[u8] {
     match self.position {
       Some(Position::HostUri(ref h, _)) => h.host,
@@ -178,7 +195,11 @@ impl<'a> HttpCursor<'a> {
       Position::HostUri(mut host, uri) => {
         match host.match_prefix_position(prefix) {
           Some(pos) => {
-            self.position = Some(Position::HostUri(host, uri));
+            if !host.at_end() {
+              self.position = Some(Position::HostUri(host, uri));
+            } else {
+              self.position = Some(Position::Uri(uri));
+            }
             Some(pos)
           },
           None => {
@@ -308,7 +329,7 @@ impl<'a> HttpCursor<'a> {
         }
 
         if uri[0] == b'~' {
-          Some((uri.len() - 1, MatchPattern::Regex(Regex::new(from_utf8(&uri[1..]).unwrap()).unwrap())))
+          parse_regex(&uri[1..]).map(|r| (uri.len() - 1, MatchPattern::Regex(r)))
         } else {
           Some((uri.len(),  MatchPattern::Prefix(uri.to_vec())))
         }
@@ -388,6 +409,23 @@ impl<'a> HttpCursor<'a> {
       },
     }
   }
+
+  /// finds the next character in `keys`, ignoring ASCII case in the host
+  pub fn match_next_char(&self, keys: &[u8]) -> Option<usize> {
+    match self.position.as_ref() {
+      None => panic!(),
+      Some(Position::HostUri(host, uri)) => {
+        host.match_next_char(keys)
+      },
+      Some(Position::Uri(uri)) => {
+        if uri.is_empty() {
+          return None;
+        }
+
+        keys.iter().position(|k| *k == uri[0])
+      },
+    }
+  }
 }
 
 #[derive(Debug,Clone)]
@@ -438,6 +476,11 @@ fn find_last_dot(input: &[u8]) -> Option<usize> {
   None
 }
 
+/// None if the pattern is not UTF-8 or not a valid regex
+pub fn parse_regex(pattern: &[u8]) -> Option<Regex> {
+  from_utf8(pattern).ok().and_then(|p| Regex::new(p).ok())
+}
+
 #[derive(Debug,Clone,PartialEq)]
 pub struct HostIterator<'a> {
   pub host: &'a[u8],
@@ -516,7 +559,7 @@ impl<'a> HostIterator<'a> {
   pub fn match_prefix(&self, prefix: &[u8]) -> Option<usize> {
     self.host.iter().rev().zip(prefix.iter().rev()).position(|(&a,&b)| {
       //println!("match_prefix: testing {} ?= {}", a as char, b as char);
-      a != b
+      !a.eq_ignore_ascii_case(&b)
     })
   }
 
@@ -541,7 +584,7 @@ impl<'a> HostIterator<'a> {
 
     match self.host.iter().rev().zip(prefix.iter().rev()).position(|(&a,&b)| {
         //println!("testing {} != {} => {}", a as char, b as char, a != b);
-        a != b
+        !a.eq_ignore_ascii_case(&b)
     }) {
       Some(pos) => {
         self.advance(pos);
@@ -550,10 +593,13 @@ impl<'a> HostIterator<'a> {
       None => {
         if prefix.len() <= self.host.len() {
           self.advance(prefix.len());
+          None
         } else {
-          self.advance(self.host.len());
+          // the host ends before the prefix
+          let pos = self.host.len();
+          self.advance(pos);
+          Some(pos)
         }
-        None
       }
     }
   }
@@ -563,7 +609,7 @@ impl<'a> HostIterator<'a> {
       return None;
     }
 
-    keys.iter().position(|k| *k == self.host[self.host.len() - 1])
+    keys.iter().position(|k| k.eq_ignore_ascii_case(&self.host[self.host.len() - 1]))
   }
 
   pub fn match_sni_wildcard(&self) -> bool {
@@ -596,14 +642,14 @@ impl<'a> HostIterator<'a> {
         None => if self.host[0] == b'/' {
           let r = &self.host[1..self.host.len() - 1];
           //println!("REGEX   making a regex from full host {}", from_utf8(r).unwrap());
-          Some((self.host.len(), MatchPattern::Regex(Regex::new(from_utf8(r).unwrap()).unwrap())))
+          parse_regex(r).map(|r| (self.host.len(), MatchPattern::Regex(r)))
         } else {
           None
         },
         Some(pos) => if self.host[pos+1] == b'/' {
           let r = &self.host[pos+2..self.host.len() - 1];
           //println!("REGEX   making a regex from {}", from_utf8(r).unwrap());
-          Some((r.len()+2, MatchPattern::Regex(Regex::new(from_utf8(r).unwrap()).unwrap())))
+          parse_regex(r).map(|regex| (r.len()+2, MatchPattern::Regex(regex)))
         } else {
           None
         }
@@ -12,7 +12,7 @@ pub mod trie;
 
 pub use self::trie::*;
 pub use self::cursor::*;
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, is_under, validate_label};
 
 #[cfg(test)]
 mod tests {
@@ -33,7 +33,7 @@ mod tests {
     assert_eq!(root.domain_insert(Vec::from(&b"*.js.example.com"[..]), 4), InsertResult::Ok);
     root.print();
     let c = HttpCursor::new(&b"www.example.com"[..], &b"/hello"[..]);
-    assert_eq!(root.insert(c, 5), InsertResult::Ok);
+    assert_eq!(root.insert(c, Vec::from(&b"www.example.com/hello"[..]), 5), InsertResult::Ok);
     root.print();
 
     let res = root.domain_lookup(&b"www.example.com"[..]).unwrap();
@@ -94,6 +94,120 @@ mod tests {
     panic!("end test");
   }
 
+  #[test]
+  fn remove() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+    root.print();
+
+    let mut expected: TrieNode<u8> = TrieNode::root();
+    assert_eq!(expected.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"api.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_remove(&Vec::from(&b"example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_remove(&Vec::from(&b"test.example.com"[..])), RemoveResult::Ok(2));
+    root.print();
+    assert_eq!(root, expected);
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).unwrap().1, 1);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"www.example.com"[..])), RemoveResult::Ok(1));
+    assert_eq!(root, TrieNode::root());
+  }
+
+  #[test]
+  fn remove_patterns() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"cdn./a[0-9]*/.example.com"[..]), 2), InsertResult::Ok);
+    root.print();
+
+    let mut expected: TrieNode<u8> = TrieNode::root();
+    assert_eq!(expected.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+
+    assert_eq!(root.domain_lookup(&b"cdn.a12.example.com"[..]).unwrap().1, 2);
+    assert_eq!(root.domain_remove(&Vec::from(&b"cdn./b[0-9]*/.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_remove(&Vec::from(&b"cdn./a[0-9]*/.example.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_lookup(&b"cdn.a12.example.com"[..]), None);
+
+    assert_eq!(root.domain_lookup(&b"api.example.com"[..]).unwrap().1, 3);
+    assert_eq!(root.domain_remove(&Vec::from(&b"*.example.com"[..])), RemoveResult::Ok(3));
+    assert_eq!(root.domain_lookup(&b"api.example.com"[..]), None);
+    assert_eq!(root.domain_remove(&Vec::from(&b"*.example.com"[..])), RemoveResult::NotFound);
+
+    root.print();
+    assert_eq!(root, expected);
+  }
+
+  #[test]
+  fn regex_case() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    // only the literal labels are lowercased
+    assert_eq!(root.domain_insert(Vec::from(&b"CDN./\\D+/.Example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_lookup(&b"cdn.abc.example.com"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"cdn.123.example.com"[..]), None);
+    assert_eq!(root.get_mut(&b"cdn./\\D+/.EXAMPLE.com"[..]), Some(&mut 1));
+    assert_eq!(root.domain_remove(&Vec::from(&b"cdn./\\d+/.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_remove(&Vec::from(&b"Cdn./\\D+/.example.com"[..])), RemoveResult::Ok(1));
+  }
+
+  #[test]
+  fn invalid_keys() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    // besides the domain checks, regex labels between slashes must parse
+    assert_eq!(root.domain_insert(vec![], 1), InsertResult::Failed(InsertError::EmptyKey));
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com."[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert(Vec::from(&b"cdn./a[0-9/.example.com"[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert(Vec::from(&b"cdn./a.example.com"[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert(Vec::from(&b"www example.com"[..]), 1), InsertResult::Failed(InsertError::InvalidCharacter));
+    assert_eq!(root.domain_insert(Vec::from(&b"cdn./a[0-9]*/.example.com"[..]), 2), InsertResult::Ok);
+
+    // the cursor does not go through the key checks
+    let cursor = HttpCursor::new(&b"cdn./b[0-9/.example.com"[..], &b"/"[..]);
+    assert_eq!(root.insert(cursor, Vec::from(&b"cdn./b[0-9/.example.com"[..]), 3), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_lookup(&b"cdn.a12.example.com"[..]).map(|kv| kv.1), Some(2));
+  }
+
+  #[test]
+  fn wildcard_after_prefix() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.js.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"api.js.example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.js.example.com"[..]), 4), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).unwrap().1, 1);
+    assert_eq!(root.domain_lookup(&b"www.js.example.com"[..]).unwrap().1, 2);
+    assert_eq!(root.domain_lookup(&b"api.js.example.com"[..]).unwrap().1, 3);
+    assert_eq!(root.domain_lookup(&b"cdn.js.example.com"[..]).unwrap().1, 4);
+    assert_eq!(root.domain_lookup(&b"cdn.www.js.example.com"[..]), None);
+  }
+
+  #[test]
+  fn wildcard_before_prefix() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"*.js.example.com"[..]), 4), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"api.js.example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.js.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    root.print();
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).unwrap().1, 1);
+    assert_eq!(root.domain_lookup(&b"www.js.example.com"[..]).unwrap().1, 2);
+    assert_eq!(root.domain_lookup(&b"api.js.example.com"[..]).unwrap().1, 3);
+    assert_eq!(root.domain_lookup(&b"cdn.js.example.com"[..]).unwrap().1, 4);
+    assert_eq!(root.domain_lookup(&b"cdn.www.js.example.com"[..]), None);
+  }
+
 /*
   #[test]
   fn remove() {
@@ -1,7 +1,7 @@
 use std::{iter,str};
 use std::fmt::Debug;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, is_under, validate_label};
 use super::cursor::*;
 
 #[derive(Clone,Debug)]
@@ -68,11 +68,11 @@ impl<V:Debug> TrieNode<V> {
     }
   }
 
-  //pub fn insert<'a>(&mut self, key: Key, value: V) -> InsertResult {
-  pub fn insert<'a>(&mut self, mut cursor: HttpCursor<'a>, value: V) -> InsertResult {
+  /// the cursor drives the descent, `key` is what the entry stores
+  pub fn insert<'a>(&mut self, mut cursor: HttpCursor<'a>, key: Key, value: V) -> InsertResult {
     //println!("insert: testing {}", cursor);
     if cursor.at_end() {
-      self.key_value = Some((self.prefix.clone(), value));
+      self.key_value = Some((key, value));
       return InsertResult::Ok;
     }
 
@@ -83,11 +83,11 @@ impl<V:Debug> TrieNode<V> {
             cursor.advance(sz);
             match self.regexes.iter().position(|reg| reg.as_str() == r.as_str()) {
               Some(c) => {
-                self.regex_children[c].insert(cursor, value)
+                self.regex_children[c].insert(cursor, key, value)
               }
               None => {
                 let mut node = TrieNode::root();
-                match node.insert(cursor, value) {
+                match node.insert(cursor, key, value) {
                   InsertResult::Ok => {
                     self.regexes.push(r);
                     self.regex_children.push(node);
@@ -98,24 +98,24 @@ impl<V:Debug> TrieNode<V> {
               }
             }
           } else {
-            panic!("next pattern should be a regex");
-            InsertResult::Failed
+            // the regex does not parse
+            InsertResult::Failed(InsertError::InvalidPattern)
           }
         }
         MatchPatternType::SniWildcard => {
           cursor.advance(1);
           if self.wildcard.is_none() {
             let mut node = TrieNode::root();
-            match node.insert(cursor, value) {
+            match node.insert(cursor, key, value) {
               InsertResult::Ok => {
-                //self.wildcard = Some(Box::new(node));
+                self.wildcard = Some(Box::new(node));
                 InsertResult::Ok
               },
               res => res
             }
           } else {
             let node = self.wildcard.as_mut().unwrap();
-            node.insert(cursor, value)
+            node.insert(cursor, key, value)
           }
         }
         MatchPatternType::Prefix(c) => {
@@ -126,12 +126,12 @@ impl<V:Debug> TrieNode<V> {
             cursor.advance(1);
             match self.child_keys.iter().position(|k| *k == c) {
               Some(index) => {
-                self.children[index].insert(cursor, value)
+                self.children[index].insert(cursor, key, value)
               },
               None => {
                 let mut node = TrieNode::root();
                 //println!("inserting new node with cursor {}", cursor);
-                match node.insert(cursor, value) {
+                match node.insert(cursor, key, value) {
                   InsertResult::Ok => {
                     self.child_keys.push(c);
                     self.children.push(node);
@@ -159,12 +159,12 @@ impl<V:Debug> TrieNode<V> {
 
                       match self.child_keys.iter().position(|k| *k == c) {
                         Some(index) => {
-                          self.children[index].insert(cursor, value)
+                          self.children[index].insert(cursor, key, value)
                         },
                         None => {
                           let mut node = TrieNode::root();
                           //println!("inserting new node with cursor {}", cursor);
-                          match node.insert(cursor, value) {
+                          match node.insert(cursor, key, value) {
                             InsertResult::Ok => {
                               self.child_keys.push(c);
                               self.children.push(node);
@@ -178,7 +178,7 @@ impl<V:Debug> TrieNode<V> {
                     MatchPatternType::Regex => {
                       if let Some((sz, MatchPattern::Regex(r))) = cursor.next_pattern() {
                         cursor.advance(sz);
-                        match new_node.insert(cursor, value) {
+                        match new_node.insert(cursor, key, value) {
                           InsertResult::Ok => {
                             self.regexes.push(r);
                             self.regex_children.push(new_node);
@@ -187,13 +187,12 @@ impl<V:Debug> TrieNode<V> {
                           res => res
                         }
                       } else {
-                        panic!("next pattern should be a regex");
-                        InsertResult::Failed
+                        InsertResult::Failed(InsertError::InvalidPattern)
                       }
                     }
                     MatchPatternType::SniWildcard => {
                       cursor.advance(1);
-                      match new_node.insert(cursor, value) {
+                      match new_node.insert(cursor, key, value) {
                         InsertResult::Ok => {
                           self.wildcard = Some(Box::new(new_node));
                           InsertResult::Ok
@@ -204,13 +203,13 @@ impl<V:Debug> TrieNode<V> {
                   }
                 } else {
                   //println!("cursor is at end: {}", cursor);
-                  self.key_value = Some((self.prefix.clone(), value));
+                  self.key_value = Some((key, value));
                   InsertResult::Ok
                 }
               }
               Some((sz, MatchPattern::Regex(r))) => {
                 cursor.advance(sz);
-                match new_node.insert(cursor, value) {
+                match new_node.insert(cursor, key, value) {
                   InsertResult::Ok => {
                     self.regexes.push(r);
                     self.regex_children.push(new_node);
@@ -221,7 +220,7 @@ impl<V:Debug> TrieNode<V> {
               }
               Some((sz, MatchPattern::SniWildcard)) => {
                 cursor.advance(1);
-                match new_node.insert(cursor, value) {
+                match new_node.insert(cursor, key, value) {
                   InsertResult::Ok => {
                     self.wildcard = Some(Box::new(new_node));
                     InsertResult::Ok
@@ -229,7 +228,7 @@ impl<V:Debug> TrieNode<V> {
                   res => res
                 }
               },
-              None => InsertResult::Failed
+              None => InsertResult::Failed(InsertError::InvalidPattern)
             }
 
           }
@@ -269,7 +268,7 @@ impl<V:Debug> TrieNode<V> {
         match cursor.next_pattern_type() {
           MatchPatternType::Prefix(c) => {
             cursor.advance(1);
-            match new_node.insert(cursor, value) {
+            match new_node.insert(cursor, key, value) {
               InsertResult::Ok => {
                 self.child_keys.push(c);
                 self.children.push(new_node);
@@ -281,7 +280,7 @@ impl<V:Debug> TrieNode<V> {
           MatchPatternType::Regex => {
             if let Some((sz, MatchPattern::Regex(r))) = cursor.next_pattern() {
               cursor.advance(sz);
-              match new_node.insert(cursor, value) {
+              match new_node.insert(cursor, key, value) {
                 InsertResult::Ok => {
                   self.regexes.push(r);
                   self.regex_children.push(new_node);
@@ -290,13 +289,12 @@ impl<V:Debug> TrieNode<V> {
                 res => res
               }
             } else {
-              panic!("next pattern should be a regex");
-              InsertResult::Failed
+              InsertResult::Failed(InsertError::InvalidPattern)
             }
           }
           MatchPatternType::SniWildcard => {
             cursor.advance(1);
-            match new_node.insert(cursor, value) {
+            match new_node.insert(cursor, key, value) {
               InsertResult::Ok => {
                 self.wildcard = Some(Box::new(new_node));
                 InsertResult::Ok
@@ -325,8 +323,7 @@ impl<V:Debug> TrieNode<V> {
           return self.key_value.as_ref();
         }
 
-        let c = cursor.next_char();
-        if let Some(index) = self.child_keys.iter().position(|k| *k == c) {
+        if let Some(index) = cursor.match_next_char(&self.child_keys) {
           let mut cursor2 = cursor.clone();
           cursor2.advance(1);
           if let Some(kv) = self.children[index].lookup(cursor2) {
@@ -356,109 +353,252 @@ impl<V:Debug> TrieNode<V> {
     }
   }
 
-  pub fn remove(&mut self, partial_key: &Key) -> RemoveResult {
-    unimplemented!();
-    /*//println!("remove: partial_key={}, local_key={}",
-      str::from_utf8(partial_key).unwrap(),
-      str::from_utf8(&self.local_key).unwrap());
-      */
+  /// collects every key matching the cursor, in the order `lookup` tries
+  /// them: children, regexes and wildcard, then the value of this node
+  pub fn lookup_all<'a>(&'a self, mut cursor: HttpCursor, matches: &mut Vec<&'a KeyValue<Key,V>>) {
+    if cursor.match_prefix_position(&self.prefix).is_some() {
+      return;
+    }
 
-    /*
-    //we check the lower level's local_key in remove_recursive,
-    //so we handle the root node here
-    match partial_key.iter().zip(self.local_key.iter()).position(|(&a,&b)| a != b) {
-      None => {
-        let local_len = self.local_key.len();
-        if partial_key.len() > local_len {
-          self.remove_recursive(&partial_key[local_len..])
-        } else if partial_key.len() == local_len {
-          if self.key_value.is_some() {
-            self.key_value = None;
-            if self.child_keys.is_empty() {
-              self.local_key = vec!();
-            }
+    if cursor.at_end() {
+      matches.extend(self.key_value.as_ref());
+      return;
+    }
 
-            RemoveResult::Ok
-          } else {
-            RemoveResult::NotFound
-          }
-        } else {
-          RemoveResult::NotFound
+    if let Some(index) = cursor.match_next_char(&self.child_keys) {
+      let mut cursor2 = cursor.clone();
+      cursor2.advance(1);
+      self.children[index].lookup_all(cursor2, matches);
+    }
+
+    for (i, r) in self.regexes.iter().enumerate() {
+      let mut cursor2 = cursor.clone();
+      if cursor2.match_regex(r) {
+        self.regex_children[i].lookup_all(cursor2, matches);
+      }
+    }
+
+    if let Some(child) = self.wildcard.as_ref() {
+      let mut cursor2 = cursor.clone();
+      if cursor2.match_sni_wildcard() {
+        child.lookup_all(cursor2, matches);
+      }
+    }
+
+    matches.extend(self.key_value.as_ref());
+  }
+
+  /// deepest key that is the host or one of its parent domains. Regexes and
+  /// wildcards are not followed, and a parent domain has to be inserted with
+  /// the "/" URI, like `domain_insert` does
+  pub fn lookup_closest(&self, mut cursor: HttpCursor) -> Option<&KeyValue<Key,V>> {
+    if cursor.match_prefix_position(&self.prefix).is_some() {
+      return None;
+    }
+
+    if cursor.at_end() {
+      return self.key_value.as_ref();
+    }
+
+    if let Some(index) = cursor.match_next_char(&self.child_keys) {
+      let mut cursor2 = cursor.clone();
+      cursor2.advance(1);
+      if let Some(kv) = self.children[index].lookup_closest(cursor2) {
+        return Some(kv);
+      }
+    }
+
+    if cursor.at_label_boundary() {
+      if let Some(index) = self.child_keys.iter().position(|&k| k == b'/') {
+        let child = &self.children[index];
+        if child.prefix.is_empty() {
+          return child.key_value.as_ref();
         }
+      }
+    }
+
+    None
+  }
+
+  /// value stored for the exact patterns of the cursor, following the path
+  /// taken by `insert`
+  pub fn get_mut_recursive(&mut self, mut cursor: HttpCursor) -> Option<&mut V> {
+    if cursor.match_prefix_position(&self.prefix).is_some() {
+      return None;
+    }
+
+    if cursor.at_end() {
+      return self.key_value.as_mut().map(|kv| &mut kv.1);
+    }
+
+    match cursor.next_pattern_type() {
+      MatchPatternType::Prefix(c) => {
+        let index = self.child_keys.iter().position(|k| *k == c)?;
+        cursor.advance(1);
+        self.children[index].get_mut_recursive(cursor)
+      },
+      MatchPatternType::SniWildcard => {
+        cursor.advance(1);
+        self.wildcard.as_mut()?.get_mut_recursive(cursor)
+      },
+      MatchPatternType::Regex => match cursor.next_pattern() {
+        Some((sz, MatchPattern::Regex(r))) => {
+          let index = self.regexes.iter().position(|reg| reg.as_str() == r.as_str())?;
+          cursor.advance(sz);
+          self.regex_children[index].get_mut_recursive(cursor)
+        },
+        _ => None,
       },
-      Some(_) => RemoveResult::NotFound
     }
-    */
+  }
 
+  pub fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.is_empty() &&
+      self.regex_children.is_empty() && self.wildcard.is_none()
   }
 
-  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult {
-    unimplemented!();
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    Iter::new(entries)
+  }
 
-    /*
-    assert_ne!(partial_key, &b""[..]);
-    match self.child_keys.iter().position(|k| *k == partial_key[0]) {
-      None => RemoveResult::NotFound,
-      Some(index) => {
-        let res = {
-          let child = &mut self.children[index];
-          match partial_key.iter().zip(child.local_key.iter()).position(|(&a,&b)| a != b) {
-            None => {
-              let child_local_len = child.local_key.len();
-              if partial_key.len() > child_local_len {
-                child.remove_recursive(&partial_key[child_local_len..])
-              } else if partial_key.len() == child_local_len {
-                if child.key_value.is_some() {
-        ////println!("removing key_value: {:?}", child.key_value);
-                  child.key_value = None;
-                  RemoveResult::Ok
-                } else {
-                  RemoveResult::NotFound
-                }
-              } else {
-                RemoveResult::NotFound
-              }
-            },
-            Some(_) => RemoveResult::NotFound
-          }
-        };
+  /// entries for the domain and its subdomains. Regexes cannot be
+  /// scoped by prefix, so this filters every entry
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
 
-        // we might have some cleanup to do
-        if res == RemoveResult::Ok {
+  pub fn len(&self) -> usize {
+    self.key_value.iter().count() +
+      self.children.iter().chain(self.regex_children.iter()).chain(self.wildcard.as_ref().map(|w| &**w))
+        .fold(0, |acc, c| acc + c.len())
+  }
 
-          if self.children[index].key_value.is_none() && self.children[index].child_keys.is_empty() {
-            self.child_keys.remove(index);
-            self.children.remove(index);
-          }
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.iter().chain(self.regex_children.iter()).chain(self.wildcard.as_ref().map(|w| &**w)) {
+      child.collect(entries);
+    }
+  }
 
-          if self.child_keys.len() == 1 {
-            match (self.key_value.is_some(), self.children[0].key_value.is_some()) {
-              // we keep the child because both current node and child have values
-              (true, true) => return res,
-              // we take the child's value
-              (false, true) => {
-                self.key_value = self.children[0].key_value.take();
-              },
-              _ => { }
-            }
+  pub fn remove<'a>(&mut self, mut cursor: HttpCursor<'a>) -> RemoveResult<V> {
+    //println!("remove: testing {}", cursor);
+    let host_len = cursor.host_len();
+
+    // the cursor is too short to match the whole prefix
+    if cursor.current_slice().len() < self.prefix.len() {
+      return RemoveResult::NotFound;
+    }
+
+    if cursor.match_prefix_position(&self.prefix).is_some() {
+      return RemoveResult::NotFound;
+    }
 
-            let _k = self.child_keys.remove(0);
-            let mut child = self.children.remove(0);
-            self.local_key.extend(child.local_key.drain(..));
-            if !child.child_keys.is_empty() {
-              self.child_keys = child.child_keys;
-              self.children = child.children;
+    let res = if cursor.at_end() {
+      self.key_value.take().into()
+    } else {
+      match cursor.next_pattern_type() {
+        MatchPatternType::Prefix(c) => {
+          cursor.advance(1);
+          match self.child_keys.iter().position(|k| *k == c) {
+            None => RemoveResult::NotFound,
+            Some(index) => {
+              let res = self.children[index].remove(cursor);
+              if self.children[index].is_empty() {
+                self.child_keys.remove(index);
+                self.children.remove(index);
+              }
+              res
             }
           }
+        },
+        MatchPatternType::Regex => {
+          if let Some((sz, MatchPattern::Regex(r))) = cursor.next_pattern() {
+            cursor.advance(sz);
+            match self.regexes.iter().position(|reg| reg.as_str() == r.as_str()) {
+              None => RemoveResult::NotFound,
+              Some(index) => {
+                let res = self.regex_children[index].remove(cursor);
+                if self.regex_children[index].is_empty() {
+                  self.regexes.remove(index);
+                  self.regex_children.remove(index);
+                }
+                res
+              }
+            }
+          } else {
+            RemoveResult::NotFound
+          }
+        },
+        MatchPatternType::SniWildcard => {
+          cursor.advance(1);
+          let (res, empty) = match self.wildcard.as_mut() {
+            None => return RemoveResult::NotFound,
+            Some(child) => {
+              let res = child.remove(cursor);
+              (res, child.is_empty())
+            }
+          };
 
-        }
+          if empty {
+            self.wildcard = None;
+          }
+          res
+        },
+      }
+    };
 
-        res
+    if let RemoveResult::Ok(_) = res {
+      self.merge_child(host_len);
+      if self.is_empty() {
+        self.prefix.clear();
       }
     }
-    */
+
+    res
   }
 
+  /// if this node has no value and only one prefix child, we merge the child in
+  /// this node, like experiment 3 does.
+  /// `host_len` is the length of the host part before matching this node's prefix.
+  /// Prefixes are matched backwards in the host but forwards in the URI, so we do not merge
+  /// across the host/URI boundary (URIs start with '/', which never appears in host prefixes)
+  fn merge_child(&mut self, host_len: usize) {
+    if self.key_value.is_some() || self.children.len() != 1 ||
+      !self.regex_children.is_empty() || self.wildcard.is_some() {
+      return;
+    }
+
+    let in_uri = host_len == 0;
+    if !in_uri && (self.child_keys[0] == b'/' || self.children[0].prefix.contains(&b'/')) {
+      return;
+    }
+
+    let c = self.child_keys.remove(0);
+    let mut child = self.children.remove(0);
+
+    if in_uri {
+      self.prefix.push(c);
+      self.prefix.extend(child.prefix.drain(..));
+    } else {
+      let mut prefix = std::mem::replace(&mut child.prefix, vec![]);
+      prefix.push(c);
+      prefix.extend(self.prefix.drain(..));
+      self.prefix = prefix;
+    }
+
+    self.key_value = child.key_value.take();
+    self.child_keys = child.child_keys;
+    self.children = child.children;
+    self.regexes = child.regexes;
+    self.regex_children = child.regex_children;
+    self.wildcard = child.wildcard;
+  }
 
   pub fn print(&self) {
     self.print_recursive(b'.', 0)
@@ -492,21 +632,53 @@ impl<V:Debug> TrieNode<V> {
   }
 }
 
+/// checks the labels of a key before inserting it: domain labels, or regexes
+/// between slashes like in `cdn./a[0-9]*/.example.com`
+fn validate_pattern(key: &[u8]) -> Result<(), InsertError> {
+  if key.is_empty() {
+    return Err(InsertError::EmptyKey);
+  }
+
+  for (i, label) in key.split(|&c| c == b'.').enumerate() {
+    if label.first() == Some(&b'/') {
+      if label.len() < 2 || label.last() != Some(&b'/') || parse_regex(&label[1..label.len()-1]).is_none() {
+        return Err(InsertError::InvalidPattern);
+      }
+    } else {
+      validate_label(label, i == 0)?;
+    }
+  }
+
+  Ok(())
+}
+
+/// lowercases the literal labels of the key, regex labels keep their case
+/// since it changes their meaning (`\D` is not `\d`)
+fn lowercase_literals(key: &mut [u8]) {
+  for label in key.split_mut(|&c| c == b'.') {
+    if label.first() != Some(&b'/') {
+      label.make_ascii_lowercase();
+    }
+  }
+}
+
 impl<V: Debug> DomainLookup<V> for TrieNode<V> {
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    lowercase_literals(&mut key);
+    if let Err(e) = validate_pattern(&key) {
+      return InsertResult::Failed(e);
+    }
+
     let cursor = HttpCursor::new(&key, &b"/"[..]);
-    self.insert(cursor, value)
+    self.insert(cursor, key.clone(), value)
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
-    //unimplemented!();
-    panic!()
-    /*
-    let mut partial_key = key.clone();
-    partial_key.reverse();
-    self.remove(&partial_key)
-    */
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let mut key = key.clone();
+    lowercase_literals(&mut key);
+    let cursor = HttpCursor::new(&key, &b"/"[..]);
+    self.remove(cursor)
   }
 
   // specific version that will handle wildcard domains
@@ -514,4 +686,23 @@ impl<V: Debug> DomainLookup<V> for TrieNode<V> {
     let cursor = HttpCursor::new(&key, &b"/"[..]);
     self.lookup(cursor)
   }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let mut key = key.to_vec();
+    lowercase_literals(&mut key);
+    let cursor = HttpCursor::new(&key, &b"/"[..]);
+    self.get_mut_recursive(cursor)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let cursor = HttpCursor::new(&key, &b"/"[..]);
+    let mut matches = Vec::new();
+    self.lookup_all(cursor, &mut matches);
+    matches
+  }
+
+  fn domain_lookup_closest(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let cursor = HttpCursor::new(&key, &b"/"[..]);
+    self.lookup_closest(cursor)
+  }
 }
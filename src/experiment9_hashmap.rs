@@ -1,11 +1,58 @@
 //! this example modifies sozu's original trie implementation to move the partial keys
 //! in the parent node
+//!
+//! The hash algorithm is a type parameter of the tree: `TrieNode::root()` uses FxHash
+//! (the hashbrown default), which is fast but vulnerable to hash flooding, while
+//! `TrieNode::root_siphash()` and `TrieNode::root_random()` use SipHash with
+//! fixed or per process random keys
 
 use std::{iter,str};
 use std::fmt::Debug;
+use std::hash::BuildHasher;
+#[allow(deprecated)]
+use std::hash::SipHasher;
+use std::sync::OnceLock;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, sort_by_specificity, validate_key};
 use hashbrown::HashMap;
+use hashbrown::hash_map::DefaultHashBuilder;
+use rand;
+
+/// FxHash, hashbrown's default hasher
+pub type FastHash = DefaultHashBuilder;
+
+/// SipHash 2-4 with configurable keys
+#[derive(Clone,Debug,PartialEq)]
+pub struct SipHash {
+  k0: u64,
+  k1: u64,
+}
+
+impl SipHash {
+  pub fn new(k0: u64, k1: u64) -> SipHash {
+    SipHash { k0, k1 }
+  }
+
+  /// random keys, generated once and then shared by every tree in the process
+  pub fn random() -> SipHash {
+    static KEYS: OnceLock<(u64, u64)> = OnceLock::new();
+
+    let &(k0, k1) = KEYS.get_or_init(|| (rand::random(), rand::random()));
+    SipHash::new(k0, k1)
+  }
+}
+
+#[allow(deprecated)]
+impl BuildHasher for SipHash {
+  type Hasher = SipHasher;
+
+  fn build_hasher(&self) -> SipHasher {
+    SipHasher::new_with_keys(self.k0, self.k1)
+  }
+}
+
+/// DNS labels are at most 63 bytes long, plus the leading dot
+const LABEL_BUFFER_SIZE: usize = 64;
 
 fn find_last_dot(input: &[u8]) -> Option<usize> {
   ////println!("find_last_dot: input = {}", from_utf8(input).unwrap());
@@ -20,35 +67,57 @@ fn find_last_dot(input: &[u8]) -> Option<usize> {
 }
 
 #[derive(Debug,PartialEq)]
-pub struct TrieNode<V> {
+pub struct TrieNode<V, S: BuildHasher = FastHash> {
   key_value: Option<KeyValue<Key,V>>,
-  children:  HashMap<Key, TrieNode<V>>,
+  children:  HashMap<Key, TrieNode<V, S>, S>,
+}
+
+impl<V:Debug> TrieNode<V, FastHash> {
+  pub fn root() -> TrieNode<V, FastHash> {
+    TrieNode::with_hasher(FastHash::default())
+  }
+}
+
+impl<V:Debug> TrieNode<V, SipHash> {
+  pub fn root_siphash(k0: u64, k1: u64) -> TrieNode<V, SipHash> {
+    TrieNode::with_hasher(SipHash::new(k0, k1))
+  }
+
+  pub fn root_random() -> TrieNode<V, SipHash> {
+    TrieNode::with_hasher(SipHash::random())
+  }
 }
 
-impl<V:Debug> TrieNode<V> {
-  pub fn new(key: Key, value: V) -> TrieNode<V> {
+impl<V:Debug, S: BuildHasher+Clone> TrieNode<V, S> {
+  pub fn new(key: Key, value: V, hash_builder: S) -> TrieNode<V, S> {
     TrieNode {
       key_value: Some((key, value)),
-      children:  HashMap::new(),
+      children:  HashMap::with_hasher(hash_builder),
     }
   }
 
-  pub fn root() -> TrieNode<V> {
+  /// creates an empty root node. Every node of the tree will use a clone of `hash_builder`
+  pub fn with_hasher(hash_builder: S) -> TrieNode<V, S> {
     TrieNode {
       key_value: None,
-      children:  HashMap::new(),
+      children:  HashMap::with_hasher(hash_builder),
     }
   }
 
   pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
-    let res = self.insert_recursive(&key, &key, value);
-    assert_ne!(res, InsertResult::Failed);
-    res
+    if key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
+
+    self.insert_recursive(&key, &key, value)
   }
 
   pub fn insert_recursive(&mut self, partial_key: &[u8], key: &Key, value: V) -> InsertResult {
     //println!("insert: key == {}", std::str::from_utf8(partial_key).unwrap());
-    assert_ne!(partial_key, &b""[..]);
+    // an empty label, like in `.example.com`
+    if partial_key.is_empty() {
+      return InsertResult::Failed(InsertError::InvalidPattern);
+    }
 
     let pos = find_last_dot(partial_key);
     match pos {
@@ -56,7 +125,7 @@ impl<V:Debug> TrieNode<V> {
         if self.children.contains_key(partial_key) {
           InsertResult::Existing
         } else {
-          let node = TrieNode::new(partial_key.to_vec(), value);
+          let node = TrieNode::new(key.clone(), value, self.children.hasher().clone());
           self.children.insert(partial_key.to_vec(), node);
           InsertResult::Ok
         }
@@ -66,10 +135,14 @@ impl<V:Debug> TrieNode<V> {
           return child.insert_recursive(&partial_key[..pos], key, value);
         }
 
-        let mut node = TrieNode::root();
-        node.insert_recursive(&partial_key[..pos], key, value);
-        self.children.insert((&partial_key[pos..]).to_vec(), node);
-        InsertResult::Ok
+        let mut node = TrieNode::with_hasher(self.children.hasher().clone());
+        match node.insert_recursive(&partial_key[..pos], key, value) {
+          InsertResult::Ok => {
+            self.children.insert((&partial_key[pos..]).to_vec(), node);
+            InsertResult::Ok
+          },
+          res => res
+        }
         /*if let Some(child) = self.children.get_mut(&partial_key[partial_key.len() - pos - 1..]) {
           return child.insert_recursive(&partial_key[..partial_key.len() - pos - 1], key, value);
         }
@@ -83,12 +156,97 @@ impl<V:Debug> TrieNode<V> {
 
   }
 
-  pub fn remove(&mut self, key: &Key) -> RemoveResult {
+  pub fn remove(&mut self, key: &Key) -> RemoveResult<V> {
     self.remove_recursive(key)
   }
 
-  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult {
-    unimplemented!()
+  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult<V> {
+    //println!("remove: key == {}", std::str::from_utf8(partial_key).unwrap());
+
+    if partial_key.len() == 0 {
+      return self.key_value.take().into();
+    }
+
+    let pos = find_last_dot(partial_key);
+    let (prefix, suffix) = match pos {
+      None => (&b""[..], partial_key),
+      Some(pos) => (&partial_key[..pos], &partial_key[pos..]),
+    };
+
+    let (res, empty) = match self.children.get_mut(suffix) {
+      None => return RemoveResult::NotFound,
+      Some(child) => {
+        let res = child.remove_recursive(prefix);
+        (res, child.key_value.is_none() && child.children.is_empty())
+      }
+    };
+
+    // the child does not hold any value or subtree anymore, we can prune it
+    if empty {
+      self.children.remove(suffix);
+    }
+
+    res
+  }
+
+  /// removes the entries for the domain and its subdomains, and returns them.
+  /// The subdomains are under one child, detached whole. A domain starting
+  /// with a dot only covers its subdomains
+  pub fn remove_subtree(&mut self, domain: &[u8]) -> Vec<KeyValue<Key,V>> {
+    let mut removed = Vec::new();
+    if domain.is_empty() {
+      removed.extend(self.key_value.take());
+      for (_, child) in self.children.drain() {
+        child.into_entries(&mut removed);
+      }
+    } else {
+      self.remove_subtree_recursive(&domain.to_ascii_lowercase(), &mut removed);
+    }
+    removed
+  }
+
+  fn remove_subtree_recursive(&mut self, partial_key: &[u8], removed: &mut Vec<KeyValue<Key,V>>) {
+    match find_last_dot(partial_key) {
+      None => {
+        // the domain is stored under its label, its subdomains under the
+        // label with a leading dot
+        let mut dotted = Vec::with_capacity(partial_key.len() + 1);
+        dotted.push(b'.');
+        dotted.extend_from_slice(partial_key);
+        for label in [partial_key, &dotted[..]].iter() {
+          if let Some(child) = self.children.remove(*label) {
+            child.into_entries(removed);
+          }
+        }
+      },
+      Some(pos) => {
+        let (prefix, suffix) = (&partial_key[..pos], &partial_key[pos..]);
+        if prefix.is_empty() {
+          if let Some(child) = self.children.remove(suffix) {
+            child.into_entries(removed);
+          }
+        } else {
+          let empty = match self.children.get_mut(suffix) {
+            Some(child) => {
+              child.remove_subtree_recursive(prefix, removed);
+              child.key_value.is_none() && child.children.is_empty()
+            },
+            None => false,
+          };
+
+          if empty {
+            self.children.remove(suffix);
+          }
+        }
+      }
+    }
+  }
+
+  fn into_entries(self, entries: &mut Vec<KeyValue<Key,V>>) {
+    entries.extend(self.key_value);
+    for (_, child) in self.children {
+      child.into_entries(entries);
+    }
   }
 
   pub fn lookup(&self, partial_key: &[u8]) -> Option<&KeyValue<Key,V>> {
@@ -106,7 +264,192 @@ impl<V:Debug> TrieNode<V> {
     };
     //println!("lookup: prefix|suffix: {} | {}", std::str::from_utf8(prefix).unwrap(), std::str::from_utf8(suffix).unwrap());
 
-    self.children.get(suffix).and_then(|child| child.lookup(prefix))
+    match self.child(suffix).and_then(|child| child.lookup(prefix)) {
+      Some(kv) => Some(kv),
+      // check for wildcard: it can only replace the leftmost label,
+      // and exact labels take precedence
+      None => {
+        let wildcard = if pos.is_none() {
+          self.children.get(&b"*"[..]).and_then(|child| child.key_value.as_ref())
+        } else {
+          None
+        };
+
+        // deep wildcards come last
+        wildcard.or_else(|| self.deep_wildcard(suffix))
+      }
+    }
+  }
+
+  /// collects every key matching the host: the exact match comes first, then
+  /// the wildcards, from the deepest to the closest to the root
+  pub fn lookup_all<'a>(&'a self, partial_key: &[u8], matches: &mut Vec<&'a KeyValue<Key,V>>) {
+    if partial_key.len() == 0 {
+      matches.extend(self.key_value.as_ref());
+      return;
+    }
+
+    let pos = find_last_dot(partial_key);
+    let (prefix, suffix) = match pos {
+      None => (&b""[..], partial_key),
+      Some(pos) => (&partial_key[..pos], &partial_key[pos..]),
+    };
+
+    if let Some(child) = self.child(suffix) {
+      child.lookup_all(prefix, matches);
+    }
+
+    if pos.is_none() {
+      if let Some(child) = self.children.get(&b"*"[..]) {
+        matches.extend(child.key_value.as_ref());
+      }
+    }
+
+    matches.extend(self.deep_wildcard(suffix));
+  }
+
+  /// the `**` entry of the domain ending with this label. It is stored under
+  /// the label with a leading dot, which the leftmost label of the host lacks
+  fn deep_wildcard(&self, suffix: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let child = if suffix[0] == b'.' {
+      self.child(suffix)
+    } else {
+      self.dotted_child(suffix)
+    };
+
+    child
+      .and_then(|child| child.children.get(&b"**"[..]))
+      .and_then(|child| child.key_value.as_ref())
+  }
+
+  /// deepest key that is the host or one of its parent domains
+  pub fn lookup_closest(&self, partial_key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    if partial_key.len() == 0 {
+      return self.key_value.as_ref();
+    }
+
+    match find_last_dot(partial_key) {
+      None => self.child(partial_key).and_then(|child| child.key_value.as_ref()),
+      Some(pos) => {
+        let (prefix, suffix) = (&partial_key[..pos], &partial_key[pos..]);
+
+        // the parent domain ending with this label is stored in the child
+        // without the leading dot
+        self.child(suffix)
+          .and_then(|child| child.lookup_closest(prefix))
+          .or_else(|| self.child(&suffix[1..]).and_then(|child| child.key_value.as_ref()))
+      }
+    }
+  }
+
+  /// finds the child for a label without allocating: keys are stored
+  /// lowercased, so a label with uppercase characters is folded in a
+  /// buffer on the stack first
+  fn child(&self, label: &[u8]) -> Option<&TrieNode<V, S>> {
+    if !label.iter().any(|c| c.is_ascii_uppercase()) {
+      return self.children.get(label);
+    }
+
+    let mut buffer = [0u8; LABEL_BUFFER_SIZE];
+    if label.len() <= LABEL_BUFFER_SIZE {
+      let folded = &mut buffer[..label.len()];
+      folded.copy_from_slice(label);
+      folded.make_ascii_lowercase();
+      self.children.get(&folded[..])
+    } else {
+      self.children.get(&label.to_ascii_lowercase()[..])
+    }
+  }
+
+  /// finds the child for the label with a leading dot, folded in a buffer on
+  /// the stack like in `child`
+  fn dotted_child(&self, label: &[u8]) -> Option<&TrieNode<V, S>> {
+    let mut buffer = [0u8; LABEL_BUFFER_SIZE];
+    if label.len() < LABEL_BUFFER_SIZE {
+      let dotted = &mut buffer[..label.len() + 1];
+      dotted[0] = b'.';
+      dotted[1..].copy_from_slice(label);
+      dotted.make_ascii_lowercase();
+      self.children.get(&dotted[..])
+    } else {
+      let mut dotted = Vec::with_capacity(label.len() + 1);
+      dotted.push(b'.');
+      dotted.extend(label.iter().map(|c| c.to_ascii_lowercase()));
+      self.children.get(&dotted[..])
+    }
+  }
+
+  // exact version of `lookup`, the key must be lowercased
+  fn get_mut_recursive(&mut self, partial_key: &[u8]) -> Option<&mut V> {
+    if partial_key.len() == 0 {
+      return self.key_value.as_mut().map(|kv| &mut kv.1);
+    }
+
+    let (prefix, suffix) = match find_last_dot(partial_key) {
+      None => (&b""[..], partial_key),
+      Some(pos) => (&partial_key[..pos], &partial_key[pos..]),
+    };
+
+    self.children.get_mut(suffix).and_then(|child| child.get_mut_recursive(prefix))
+  }
+
+  /// every entry stored in the tree, in no particular order
+  pub fn iter(&self) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains, found by following the
+  /// labels of the domain
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    if domain.is_empty() {
+      return self.iter();
+    }
+
+    let mut entries = Vec::new();
+    self.collect_under(domain, &mut entries);
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.children.values().fold(self.key_value.iter().count(), |acc, c| acc + c.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.values().all(|c| c.is_empty())
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.values() {
+      child.collect(entries);
+    }
+  }
+
+  fn collect_under<'a>(&'a self, partial_key: &[u8], entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    // the domain started with a dot, this node holds its subdomains
+    if partial_key.is_empty() {
+      return self.collect(entries);
+    }
+
+    match find_last_dot(partial_key) {
+      None => {
+        // the domain is stored under its label, its subdomains under the
+        // label with a leading dot
+        if let Some(child) = self.child(partial_key) {
+          child.collect(entries);
+        }
+        if let Some(child) = self.dotted_child(partial_key) {
+          child.collect(entries);
+        }
+      },
+      Some(pos) => {
+        if let Some(child) = self.child(&partial_key[pos..]) {
+          child.collect_under(&partial_key[..pos], entries);
+        }
+      }
+    }
   }
 
   pub fn print(&self) {
@@ -131,10 +474,15 @@ impl<V:Debug> TrieNode<V> {
   }
 }
 
-impl<V: Debug> DomainLookup<V> for TrieNode<V> {
+impl<V: Debug, S: BuildHasher+Clone> DomainLookup<V> for TrieNode<V, S> {
 
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     self.insert(key, value)
     /*
     let mut partial_key = key.clone();
@@ -144,8 +492,8 @@ impl<V: Debug> DomainLookup<V> for TrieNode<V> {
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
-    unimplemented!()
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    self.remove(&key.to_ascii_lowercase())
     /*
     let mut partial_key = key.clone();
     partial_key.reverse();
@@ -163,11 +511,27 @@ impl<V: Debug> DomainLookup<V> for TrieNode<V> {
       */
 
   }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    self.get_mut_recursive(&key.to_ascii_lowercase())
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut matches = Vec::new();
+    self.lookup_all(key, &mut matches);
+    sort_by_specificity(&mut matches);
+    matches
+  }
+
+  fn domain_lookup_closest(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    self.lookup_closest(key)
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use std::hash::Hasher;
 
   #[test]
   fn insert() {
@@ -200,14 +564,14 @@ mod tests {
 
     println!("before remove");
     root.print();
-    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok(2));
     println!("after remove");
     root.print();
     println!("expected");
     root2.print();
     assert_eq!(root, root2);
 
-    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok(3));
     println!("after remove");
     root.print();
     println!("expected");
@@ -238,7 +602,7 @@ mod tests {
     assert_eq!(root2.insert(Vec::from(&b"abce"[..]), 2), InsertResult::Ok);
 
     root2.print();
-    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok);
+    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok(3));
 
     let mut expected: TrieNode<u8> = TrieNode::root();
 
@@ -278,7 +642,7 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
-    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
     println!("after remove");
     root.print();
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
@@ -286,4 +650,74 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
   }
+
+  #[test]
+  fn wildcard() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.test.example.com"[..]), 3), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.org"[..]), 4), InsertResult::Ok);
+
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"api.example.com"[..]), Some(&((&b"*.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), Some(&((&b"*.example.com"[..]).to_vec(), 1)));
+    assert_eq!(root.domain_lookup(&b"api.test.example.com"[..]), Some(&((&b"*.test.example.com"[..]).to_vec(), 3)));
+    assert_eq!(root.domain_lookup(&b"api.www.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"example.org"[..]), Some(&((&b"*.org"[..]).to_vec(), 4)));
+    assert_eq!(root.domain_lookup(&b"www.example.org"[..]), None);
+  }
+
+  #[test]
+  fn prune() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"api.test.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.test.example.com"[..]), 3), InsertResult::Ok);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"test.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_remove(&Vec::from(&b"www.example.org"[..])), RemoveResult::NotFound);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"api.test.example.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_remove(&Vec::from(&b"api.test.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_lookup(&b"api.test.example.com"[..]), Some(&((&b"*.test.example.com"[..]).to_vec(), 3)));
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"*.test.example.com"[..])), RemoveResult::Ok(3));
+    assert_eq!(root.domain_lookup(&b"api.test.example.com"[..]), None);
+
+    let mut expected: TrieNode<u8> = TrieNode::root();
+    assert_eq!(expected.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root, expected);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"www.example.com"[..])), RemoveResult::Ok(1));
+    assert_eq!(root, TrieNode::root());
+  }
+
+  #[test]
+  fn hashers() {
+    let mut fast: TrieNode<u8> = TrieNode::root();
+    let mut keyed: TrieNode<u8, SipHash> = TrieNode::root_siphash(0x0123456789abcdef, 0xfedcba9876543210);
+    let mut random: TrieNode<u8, SipHash> = TrieNode::root_random();
+    assert_eq!(SipHash::random(), SipHash::random());
+
+    // the caller's keys select the hash function
+    let hash = |hasher: SipHash| {
+      let mut h = hasher.build_hasher();
+      h.write(b"example.com");
+      h.finish()
+    };
+    assert_eq!(hash(SipHash::new(1, 2)), hash(SipHash::new(1, 2)));
+    assert_ne!(hash(SipHash::new(1, 2)), hash(SipHash::new(2, 1)));
+
+    assert_eq!(fast.domain_insert(Vec::from(&b"*.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(keyed.domain_insert(Vec::from(&b"*.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(random.domain_insert(Vec::from(&b"*.example.com"[..]), 1), InsertResult::Ok);
+
+    assert_eq!(fast.domain_lookup(&b"www.example.com"[..]), Some(&((&b"*.example.com"[..]).to_vec(), 1)));
+    assert_eq!(keyed.domain_lookup(&b"www.example.com"[..]), Some(&((&b"*.example.com"[..]).to_vec(), 1)));
+    assert_eq!(random.domain_lookup(&b"www.example.com"[..]), Some(&((&b"*.example.com"[..]).to_vec(), 1)));
+  }
 }
@@ -28,22 +28,33 @@ pub fn gen_seed_wilcard_domain(top_level_domain: &str) -> Vec<u8> {
 /// 1/3 domain_text.uuid.tld
 /// 1/3 *.uuid.tld
 pub fn seed_bench_trie<T: DomainLookup<u8>>(root: &mut T, nb_elems_seed: i32) {
+    for (domain, value) in gen_bench_domains(nb_elems_seed) {
+        root.domain_insert(domain, value);
+    }
+}
+
+/// Generate the domains inserted by `seed_bench_trie`, with their value, so
+/// they can be removed afterwards
+pub fn gen_bench_domains(nb_elems_seed: i32) -> Vec<(Vec<u8>, u8)> {
     let mut random = XorShiftRng::new_unseeded();
     let domains = gen_domains!();
     let tlds = gen_tld!();
     let mut h = HashSet::new();
+    let mut v = Vec::new();
 
     for tld in tlds.iter() {
         for _ in 0..nb_elems_seed / 3 {
-            root.domain_insert(gen_uuid_seed_domain(tld), 1);
+            v.push((gen_uuid_seed_domain(tld), 1));
             let text_domain = gen_text_seed_domain(tld, &domains, &mut random);
             if !h.contains(&text_domain) {
               h.insert(text_domain.clone());
-              root.domain_insert(text_domain, 2);
+              v.push((text_domain, 2));
             }
-            root.domain_insert(gen_seed_wilcard_domain(tld), 2);
+            v.push((gen_seed_wilcard_domain(tld), 2));
         }
     }
+
+    v
 }
 
 pub fn seed_known_domain<T: DomainLookup<u8>>(root: &mut T) {
@@ -1,18 +1,44 @@
 //! this example uses a list of ACL tested in linear order
 
-use super::{Key, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, InsertResult, RemoveResult, DomainLookup, Iter, is_under, validate_key};
 use hashbrown::HashMap;
 
+/// DNS names are at most 253 bytes long
+const HOST_BUFFER_SIZE: usize = 256;
+
 pub struct Map(HashMap<Vec<u8>, (Vec<u8>, u8)>);
 
 impl Map {
   pub fn new() -> Self {
     Map(HashMap::new())
   }
+
+  /// every entry, in no particular order
+  pub fn iter(&self) -> Iter<u8> {
+    Iter::new(self.0.values().collect())
+  }
+
+  /// entries for the domain and its subdomains, found by filtering every entry
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<u8> {
+    Iter::new(self.0.values().filter(|kv| is_under(&kv.0, domain)).collect())
+  }
+
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
 }
 
 impl DomainLookup<u8> for Map {
-  fn domain_insert(&mut self, key: Vec<u8>, value: u8) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Vec<u8>, value: u8) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     //let mut partial_key = key.clone();
     //partial_key.reverse();
     //self.0.insert(partial_key, (key.clone(), value));
@@ -24,10 +50,98 @@ impl DomainLookup<u8> for Map {
     //let mut partial_key = key.to_vec();
     //partial_key.reverse();
     //self.0.get(&partial_key)
-    self.0.get(key)
+    if !key.iter().any(|c| c.is_ascii_uppercase()) {
+      return self.0.get(key);
+    }
+
+    // keys are stored lowercased, fold the host on the stack
+    let mut buffer = [0u8; HOST_BUFFER_SIZE];
+    if key.len() <= HOST_BUFFER_SIZE {
+      let folded = &mut buffer[..key.len()];
+      folded.copy_from_slice(key);
+      folded.make_ascii_lowercase();
+      self.0.get(&folded[..])
+    } else {
+      self.0.get(&key.to_ascii_lowercase()[..])
+    }
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut u8> {
+    self.0.get_mut(&key.to_ascii_lowercase()[..]).map(|kv| &mut kv.1)
   }
 
-  fn domain_remove(&mut self, _key: &Key) -> RemoveResult {
-    unimplemented!();
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<u8> {
+    self.0.remove(&key.to_ascii_lowercase()[..]).into()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use InsertError;
+
+  #[test]
+  fn remove() {
+    let mut root = Map::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"test.example.com"[..]), 2), InsertResult::Ok);
+
+    assert_eq!(root.domain_remove(&Vec::from(&b"TEST.example.com"[..])), RemoveResult::Ok(2));
+    assert_eq!(root.domain_remove(&Vec::from(&b"test.example.com"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]), None);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), Some(&((&b"www.example.com"[..]).to_vec(), 1)));
+  }
+
+  #[test]
+  fn mixed_case() {
+    let mut root = Map::new();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"Washtucna.obeliskoide.org"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.EXAMPLE.com"[..]), 2), InsertResult::Ok);
+
+    assert_eq!(root.domain_lookup(&b"WASHTUCNA.OBELISKOIDE.ORG"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"washtucna.obeliskoide.org"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"WwW.eXaMpLe.CoM"[..]), Some(&(b"www.example.com".to_vec(), 2)));
+    assert_eq!(root.domain_lookup(&b"www.example.net"[..]), None);
+  }
+
+  #[test]
+  fn upsert() {
+    let mut root = Map::new();
+
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 1), Ok(None));
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 2), Ok(Some(1)));
+    assert_eq!(root.domain_upsert(Vec::from(&b"www..example.com"[..]), 3), Err(InsertError::InvalidPattern));
+    *root.domain_lookup_mut(&b"WWW.example.com"[..]).unwrap() = 3;
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(3));
+  }
+
+  #[test]
+  fn invalid_keys() {
+    let mut root = Map::new();
+
+    assert_eq!(root.domain_insert(vec![], 1), InsertResult::Failed(InsertError::EmptyKey));
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com."[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert(Vec::from(&b"www.*.com"[..]), 1), InsertResult::Failed(InsertError::InvalidPattern));
+    assert_eq!(root.domain_insert(Vec::from(&b"www example.com"[..]), 1), InsertResult::Failed(InsertError::InvalidCharacter));
+    assert!(root.is_empty());
+  }
+
+  #[test]
+  fn iter() {
+    let mut root = Map::new();
+
+    assert!(root.is_empty());
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 3), InsertResult::Ok);
+
+    let mut values: Vec<u8> = root.iter().map(|(_, value)| *value).collect();
+    values.sort();
+    assert_eq!(values, vec![1, 2, 3]);
+    assert_eq!(root.len(), 3);
+    assert_eq!(root.iter_under(&b"Example.COM"[..]).count(), 2);
+    assert_eq!(root.iter_under(&b"xample.com"[..]).count(), 0);
   }
 }
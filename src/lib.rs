@@ -18,10 +18,16 @@ pub mod experiment6_fst_bitvec;
 pub mod experiment7_regexset;
 pub mod experiment8_trie_cursor;
 pub mod experiment9_hashmap;
-pub mod experiment10_hashmap_siphash;
+pub mod experiment11_art;
+pub mod experiment12_double_array;
+pub mod experiment13_burst_trie;
+pub mod experiment14_interned;
+pub mod experiment15_louds;
 pub mod linear;
 pub mod hashmap;
 
+use std::cmp::Reverse;
+
 pub type Key = Vec<u8>;
 pub type KeyValue<K,V> = (K,V);
 
@@ -29,21 +35,598 @@ pub type KeyValue<K,V> = (K,V);
 pub enum InsertResult {
   Ok,
   Existing,
-  Failed
+  Failed(InsertError),
+}
+
+/// why a key was rejected instead of inserted
+#[derive(Clone,Debug,PartialEq)]
+pub enum InsertError {
+  EmptyKey,
+  /// an empty label (`example..com`, `example.com.`), a wildcard that is not
+  /// the first label, or a pattern that does not parse
+  InvalidPattern,
+  /// a byte that is not a letter, a digit, '-', '_', '.' or '*'
+  InvalidCharacter,
+  /// the structure cannot store the key next to the existing ones
+  Conflict,
+  /// the structure was finished and does not accept new keys
+  AlreadyFinished,
 }
 
+/// `Ok` holds the value of the removed key, so the caller can release what
+/// was attached to the route
 #[derive(Debug,PartialEq)]
-pub enum RemoveResult {
-  Ok,
+pub enum RemoveResult<V> {
+  Ok(V),
   NotFound,
+  /// the structure cannot remove keys anymore, like the exp 6 and 7
+  /// machines once finished. The key may still be there
+  Unsupported,
+}
+
+impl<V> From<Option<KeyValue<Key,V>>> for RemoveResult<V> {
+  fn from(removed: Option<KeyValue<Key,V>>) -> RemoveResult<V> {
+    match removed {
+      Some((_, value)) => RemoveResult::Ok(value),
+      None             => RemoveResult::NotFound,
+    }
+  }
 }
 
+/// domain names are case insensitive: implementations store keys lowercased,
+/// and fold the ASCII case of the host when looking it up
 pub trait DomainLookup<V> {
   fn domain_insert(&mut self, key: Key, value: V) -> InsertResult;
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult;
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V>;
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>>;
+
+  /// value stored for exactly this key (wildcards are matched as text), to
+  /// update it in place
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V>;
+
+  /// inserts the value, or replaces the value of an existing key without
+  /// removing it first, so the host is routed at all times. Returns the
+  /// previous value, or why a new key was rejected
+  fn domain_upsert(&mut self, key: Key, value: V) -> Result<Option<V>, InsertError> {
+    match self.get_mut(&key) {
+      Some(previous) => Ok(Some(::std::mem::replace(previous, value))),
+      None => match self.domain_insert(key, value) {
+        InsertResult::Failed(e) => Err(e),
+        _ => Ok(None),
+      },
+    }
+  }
+
+  /// value of the key matching the host, like `domain_lookup`, to update it
+  /// in place. The default version looks up the host, then gets the key
+  fn domain_lookup_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    let key = self.domain_lookup(key).map(|kv| kv.0.clone())?;
+    self.get_mut(&key)
+  }
+
+  /// every key matching the host, from the most specific to the least specific.
+  /// Implementations that cannot find more than one match return the result
+  /// of `domain_lookup`
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    self.domain_lookup(key).into_iter().collect()
+  }
+
+  /// the key of the host or of its deepest registered parent domain, so
+  /// `a.b.example.com` finds `example.com` if nothing deeper exists. Wildcards
+  /// are not parent domains. The default version looks up the host, then each
+  /// of its parents
+  fn domain_lookup_closest(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let mut host = key;
+    loop {
+      // `example.com.` or `..` end with an empty host, that cannot match
+      if host.is_empty() {
+        return None;
+      }
+
+      if let Some(kv) = self.domain_lookup(host) {
+        if specificity(&kv.0) == 0 {
+          return Some(kv);
+        }
+      }
+
+      match host.iter().position(|&c| c == b'.') {
+        Some(pos) => host = &host[pos + 1..],
+        None      => return None,
+      }
+    }
+  }
+}
+
+/// rank of a key in the results of `domain_lookup_all`: exact domains come
+/// first, then single label wildcards (`*.example.com`), then deep wildcards
+/// (`**.example.com`, matching the domain and all of its subdomains)
+pub fn specificity(key: &[u8]) -> usize {
+  if key.starts_with(b"**") {
+    2
+  } else if key.contains(&b'*') {
+    1
+  } else {
+    0
+  }
+}
+
+// with the same rank, the longest key is the most specific
+fn precedence(key: &[u8]) -> (usize, Reverse<usize>) {
+  (specificity(key), Reverse(key.len()))
+}
+
+/// stable sort of matching keys, from the most specific to the least specific
+pub fn sort_by_specificity<V>(matches: &mut Vec<&KeyValue<Key,V>>) {
+  matches.sort_by_key(|kv| precedence(&kv.0));
+}
+
+/// the most specific of two matches, or the first one if they are as specific
+pub fn most_specific<'a, V>(a: Option<&'a KeyValue<Key,V>>, b: Option<&'a KeyValue<Key,V>>) -> Option<&'a KeyValue<Key,V>> {
+  match (a, b) {
+    (Some(a), Some(b)) => if precedence(&b.0) < precedence(&a.0) { Some(b) } else { Some(a) },
+    (a, None)          => a,
+    (None, b)          => b,
+  }
+}
+
+/// iterator over the entries of a routing structure, as `(&Key, &V)`
+pub struct Iter<'a, V: 'a> {
+  entries: ::std::vec::IntoIter<&'a KeyValue<Key,V>>,
+}
+
+impl<'a, V> Iter<'a, V> {
+  pub fn new(entries: Vec<&'a KeyValue<Key,V>>) -> Iter<'a, V> {
+    Iter { entries: entries.into_iter() }
+  }
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+  type Item = (&'a Key, &'a V);
+
+  fn next(&mut self) -> Option<(&'a Key, &'a V)> {
+    self.entries.next().map(|kv| (&kv.0, &kv.1))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.entries.size_hint()
+  }
+}
+
+/// true if the key is the domain or one of its subdomains (wildcards included),
+/// for the prefix-scoped iterators. A domain starting with a dot only covers
+/// its subdomains, and every key is under an empty domain
+pub fn is_under(key: &[u8], domain: &[u8]) -> bool {
+  if domain.is_empty() {
+    return true;
+  }
+
+  if domain[0] == b'.' {
+    return key.len() > domain.len() && key[key.len() - domain.len()..].eq_ignore_ascii_case(domain);
+  }
+
+  key.len() >= domain.len()
+    && key[key.len() - domain.len()..].eq_ignore_ascii_case(domain)
+    && (key.len() == domain.len() || key[key.len() - domain.len() - 1] == b'.')
+}
+
+/// true if every key continuing the reversed domain with `next` is under the
+/// domain, for the subtree removals: the next byte starts a new label, or the
+/// domain starts with a dot and only covers subdomains
+pub fn covers_subtree(domain: &[u8], next: u8) -> bool {
+  domain.first().map(|&c| c == b'.').unwrap_or(true) || next == b'.'
+}
+
+/// checks that the key is a domain name before inserting it: labels made of
+/// ASCII letters, digits, '-' and '_', with an optional `*` or `**` wildcard
+/// as the first label
+pub fn validate_key(key: &[u8]) -> Result<(), InsertError> {
+  if key.is_empty() {
+    return Err(InsertError::EmptyKey);
+  }
+
+  for (i, label) in key.split(|&c| c == b'.').enumerate() {
+    validate_label(label, i == 0)?;
+  }
+
+  Ok(())
+}
+
+/// checks one label of a domain name, a wildcard is only allowed in the first one
+pub fn validate_label(label: &[u8], first: bool) -> Result<(), InsertError> {
+  if label.is_empty() {
+    return Err(InsertError::InvalidPattern);
+  }
+
+  if !label.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c == b'*') {
+    return Err(InsertError::InvalidCharacter);
+  }
+
+  if label.contains(&b'*') && !(first && (label == &b"*"[..] || label == &b"**"[..])) {
+    return Err(InsertError::InvalidPattern);
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// builds an empty structure for the shared tests
+  type Root = fn() -> Box<dyn DomainLookup<u8>>;
+
+  /// the structures that take domain keys as they are, with the name shown
+  /// when a check fails. The ones that must be finished or take other keys
+  /// (exp 6 and 7, the hashmap) test the same behaviour in their own module
+  const ROOTS: [(&str, Root); 15] = [
+    ("sozu",         || Box::new(sozu_trie::TrieNode::root())),
+    ("exp1",         || Box::new(experiment1_trie::TrieNode::root())),
+    ("exp2",         || Box::new(experiment2_trie::TrieNode::root())),
+    ("exp3",         || Box::new(experiment3_trie::TrieNode::root())),
+    // after `finish`, the changes go to the overlay
+    ("exp4",         || {
+      let mut root = experiment4_fst::Machine::new();
+      root.finish().unwrap();
+      Box::new(root)
+    }),
+    ("exp5",         || Box::new(experiment5_trie_bitvec::TrieNode::root())),
+    ("exp8",         || Box::new(experiment8_trie_cursor::TrieNode::root())),
+    ("exp9",         || Box::new(experiment9_hashmap::TrieNode::root())),
+    ("exp9 siphash", || Box::new(experiment9_hashmap::TrieNode::root_random())),
+    ("exp11",        || Box::new(experiment11_art::Art::root())),
+    ("exp12",        || Box::new(experiment12_double_array::DoubleArray::root())),
+    ("exp13",        || Box::new(experiment13_burst_trie::BurstTrie::root())),
+    ("exp14",        || Box::new(experiment14_interned::InternedTrie::root())),
+    ("exp15",        || Box::new(experiment15_louds::LoudsTrie::root())),
+    ("linear",       || Box::new(linear::List::root())),
+  ];
+
+  /// these only see the match `domain_lookup` returns, so they are not
+  /// checked with several matching keys
+  const SINGLE_MATCH: [&str; 4] = ["exp1", "exp2", "exp4", "exp5"];
+
+  const ENTRIES: [(&[u8], u8); 5] = [
+    (b"example.com", 1),
+    (b"www.example.com", 2),
+    (b"*.example.com", 3),
+    (b"ample.com", 4),
+    (b"example.org", 5),
+  ];
+
+  fn fill<T: DomainLookup<u8> + ?Sized>(root: &mut T) {
+    for &(key, value) in ENTRIES.iter() {
+      assert_eq!(root.domain_insert(Vec::from(key), value), InsertResult::Ok);
+    }
+  }
+
+  // iter, iter_under, len and is_empty are inherent methods, so this is a macro
+  macro_rules! check_iter {
+    ($name:expr, $root:expr) => {{
+      let mut root = $root;
+      assert!(root.is_empty(), "{}", $name);
+      assert_eq!(root.len(), 0, "{}", $name);
+      assert_eq!(root.iter().count(), 0, "{}", $name);
+      fill(&mut root);
+      check_iter!(@filled $name, root);
+    }};
+    (@filled $name:expr, $root:ident) => {{
+      let sorted = |it: Iter<u8>| {
+        let mut values: Vec<u8> = it.map(|(_, value)| *value).collect();
+        values.sort();
+        values
+      };
+
+      assert!(!$root.is_empty(), "{}", $name);
+      assert_eq!($root.len(), 5, "{}", $name);
+      assert_eq!(sorted($root.iter()), vec![1, 2, 3, 4, 5], "{}", $name);
+      for (key, value) in $root.iter() {
+        assert!(ENTRIES.iter().any(|&(k, v)| k == &key[..] && v == *value), "{}: unexpected entry {:?}", $name, key);
+      }
+
+      assert_eq!(sorted($root.iter_under(&b"example.com"[..])), vec![1, 2, 3], "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b"Example.COM"[..])), vec![1, 2, 3], "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b"www.example.com"[..])), vec![2], "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b".example.com"[..])), vec![2, 3], "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b"com"[..])), vec![1, 2, 3, 4], "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b"xample.com"[..])), Vec::<u8>::new(), "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b"example.net"[..])), Vec::<u8>::new(), "{}", $name);
+      assert_eq!(sorted($root.iter_under(&b""[..])), vec![1, 2, 3, 4, 5], "{}", $name);
+    }};
+  }
+
+  #[test]
+  fn iter() {
+    check_iter!("sozu", sozu_trie::TrieNode::root());
+    check_iter!("exp1", experiment1_trie::TrieNode::root());
+    check_iter!("exp2", experiment2_trie::TrieNode::root());
+    check_iter!("exp3", experiment3_trie::TrieNode::root());
+    check_iter!("exp4", {
+      let mut root = experiment4_fst::Machine::new();
+      root.finish().unwrap();
+      root
+    });
+    check_iter!("exp5", experiment5_trie_bitvec::TrieNode::root());
+    check_iter!("exp8", experiment8_trie_cursor::TrieNode::root());
+    check_iter!("exp9", experiment9_hashmap::TrieNode::root());
+    check_iter!("exp9 siphash", experiment9_hashmap::TrieNode::root_random());
+    check_iter!("exp11", experiment11_art::Art::root());
+    check_iter!("exp12", experiment12_double_array::DoubleArray::root());
+    check_iter!("exp13", experiment13_burst_trie::BurstTrie::root());
+    check_iter!("exp14", experiment14_interned::InternedTrie::root());
+    check_iter!("exp15", experiment15_louds::LoudsTrie::root());
+    check_iter!("linear", linear::List::root());
+  }
+
+  macro_rules! check_remove_subtree {
+    ($name:expr, $root:expr) => {{
+      let mut root = $root;
+      fill(&mut root);
+      assert_eq!(root.domain_insert(Vec::from(&b"anexample.com"[..]), 6), InsertResult::Ok, "{}", $name);
+      assert_eq!(root.domain_insert(Vec::from(&b"a.b.example.com"[..]), 7), InsertResult::Ok, "{}", $name);
+      assert_eq!(root.domain_insert(Vec::from(&b"**.example.com"[..]), 8), InsertResult::Ok, "{}", $name);
+
+      let mut removed = |domain: &[u8]| {
+        let mut values: Vec<u8> = root.remove_subtree(domain).into_iter().map(|(_, value)| value).collect();
+        values.sort();
+        values
+      };
+
+      // the subdomains only
+      assert_eq!(removed(&b".example.com"[..]), vec![2, 3, 7, 8], "{}", $name);
+      // the domain is a suffix of other keys, but not on a label boundary
+      assert_eq!(removed(&b"ample.com"[..]), vec![4], "{}", $name);
+      assert_eq!(removed(&b"Example.COM"[..]), vec![1], "{}", $name);
+      assert_eq!(removed(&b"example.com"[..]), Vec::<u8>::new(), "{}", $name);
+      assert_eq!(removed(&b"example.net"[..]), Vec::<u8>::new(), "{}", $name);
+
+      assert_eq!(root.len(), 2, "{}", $name);
+      assert_eq!(root.domain_lookup(&b"anexample.com"[..]).map(|kv| kv.1), Some(6), "{}", $name);
+      assert_eq!(root.domain_lookup(&b"example.org"[..]).map(|kv| kv.1), Some(5), "{}", $name);
+      assert_eq!(root.domain_lookup(&b"www.example.com"[..]), None, "{}", $name);
+      assert_eq!(root.domain_lookup(&b"example.com"[..]), None, "{}", $name);
+
+      assert_eq!(root.remove_subtree(&b""[..]).len(), 2, "{}", $name);
+      assert!(root.is_empty(), "{}", $name);
+      assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok, "{}", $name);
+      assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(2), "{}", $name);
+    }};
+  }
+
+  #[test]
+  fn remove_subtree() {
+    check_remove_subtree!("sozu", sozu_trie::TrieNode::root());
+    check_remove_subtree!("exp3", experiment3_trie::TrieNode::root());
+    check_remove_subtree!("exp9", experiment9_hashmap::TrieNode::root());
+    check_remove_subtree!("exp9 siphash", experiment9_hashmap::TrieNode::root_random());
+  }
+
+  const KEYS: [(&[u8], u8); 3] = [
+    (b"Washtucna.obeliskoide.org", 1),
+    (b"www.EXAMPLE.com", 2),
+    (b"*.AllDomains.org", 3),
+  ];
+
+  fn check_mixed_case<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    for &(key, value) in KEYS.iter() {
+      assert_eq!(root.domain_insert(key.to_vec(), value), InsertResult::Ok, "{}", name);
+    }
+
+    {
+      let value = |host: &[u8]| root.domain_lookup(host).map(|kv| kv.1);
+      assert_eq!(value(&b"washtucna.obeliskoide.org"[..]), Some(1), "{}", name);
+      assert_eq!(value(&b"WASHTUCNA.OBELISKOIDE.ORG"[..]), Some(1), "{}", name);
+      assert_eq!(value(&b"Washtucna.Obeliskoide.Org"[..]), Some(1), "{}", name);
+      assert_eq!(value(&b"www.example.com"[..]), Some(2), "{}", name);
+      assert_eq!(value(&b"WwW.eXaMpLe.CoM"[..]), Some(2), "{}", name);
+      assert_eq!(value(&b"www.example.net"[..]), None, "{}", name);
+      assert_eq!(value(&b"test.alldomains.org"[..]), Some(3), "{}", name);
+      assert_eq!(value(&b"TEST.ALLDOMAINS.ORG"[..]), Some(3), "{}", name);
+      assert_eq!(value(&b"blah.Test.AllDomains.org"[..]), None, "{}", name);
+    }
+
+    // keys are normalised on insert
+    let stored = &root.domain_lookup(&b"WWW.example.com"[..]).unwrap().0;
+    assert!(!stored.iter().any(|c| c.is_ascii_uppercase()), "{}", name);
+    assert_eq!(root.domain_remove(&Vec::from(&b"WWW.Example.com"[..])), RemoveResult::Ok(2), "{}", name);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]), None, "{}", name);
+  }
+
+  #[test]
+  fn mixed_case() {
+    for &(name, root) in ROOTS.iter() {
+      check_mixed_case(name, &mut *root());
+    }
+  }
+
+  // hosts with empty labels match nothing, and do not panic
+  fn check_empty_hosts<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok, "{}", name);
+
+    for host in [&b""[..], &b"."[..], &b"www.example.com."[..]].iter() {
+      assert_eq!(root.domain_lookup(host), None, "{}: {:?}", name, host);
+      assert!(root.domain_lookup_all(host).is_empty(), "{}: {:?}", name, host);
+    }
+  }
+
+  #[test]
+  fn empty_hosts() {
+    for &(name, root) in ROOTS.iter() {
+      check_empty_hosts(name, &mut *root());
+    }
+  }
+
+  fn values<T: DomainLookup<u8> + ?Sized>(root: &T, host: &[u8]) -> Vec<u8> {
+    root.domain_lookup_all(host).iter().map(|kv| kv.1).collect()
+  }
+
+  fn check_all<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 1), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 3), InsertResult::Ok, "{}", name);
+
+    assert_eq!(values(root, &b"www.example.com"[..]), vec![2, 1], "{}", name);
+    assert_eq!(values(root, &b"WWW.example.com"[..]), vec![2, 1], "{}", name);
+    assert_eq!(values(root, &b"test.example.com"[..]), vec![1], "{}", name);
+    assert_eq!(values(root, &b"example.com"[..]), vec![3], "{}", name);
+    assert_eq!(values(root, &b"blah.www.example.com"[..]), Vec::<u8>::new(), "{}", name);
+    assert_eq!(values(root, &b"www.example.org"[..]), Vec::<u8>::new(), "{}", name);
+  }
+
+  #[test]
+  fn lookup_all() {
+    for &(name, root) in ROOTS.iter().filter(|&&(name, _)| !SINGLE_MATCH.contains(&name)) {
+      check_all(name, &mut *root());
+    }
+  }
+
+  fn closest<T: DomainLookup<u8> + ?Sized>(root: &T, host: &[u8]) -> Option<u8> {
+    root.domain_lookup_closest(host).map(|kv| kv.1)
+  }
+
+  fn check_closest<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    assert_eq!(root.domain_insert(Vec::from(&b"example.com"[..]), 1), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 3), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"ample.com"[..]), 4), InsertResult::Ok, "{}", name);
+
+    assert_eq!(closest(root, &b"www.example.com"[..]), Some(2), "{}", name);
+    assert_eq!(closest(root, &b"example.com"[..]), Some(1), "{}", name);
+    assert_eq!(closest(root, &b"test.example.com"[..]), Some(1), "{}", name);
+    assert_eq!(closest(root, &b"a.b.example.com"[..]), Some(1), "{}", name);
+    assert_eq!(closest(root, &b"a.www.Example.com"[..]), Some(2), "{}", name);
+    assert_eq!(closest(root, &b"xample.com"[..]), None, "{}", name);
+    assert_eq!(closest(root, &b"example.org"[..]), None, "{}", name);
+    assert_eq!(closest(root, &b"example.com."[..]), None, "{}", name);
+    assert_eq!(closest(root, &b"."[..]), None, "{}", name);
+    assert_eq!(closest(root, &b".."[..]), None, "{}", name);
+    assert_eq!(closest(root, &b""[..]), None, "{}", name);
+  }
+
+  #[test]
+  fn lookup_closest() {
+    for &(name, root) in ROOTS.iter().filter(|&&(name, _)| !SINGLE_MATCH.contains(&name)) {
+      check_closest(name, &mut *root());
+    }
+  }
+
+  fn check_deep_wildcards<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    assert_eq!(root.domain_insert(Vec::from(&b"**.example.com"[..]), 1), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 3), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"**.deep.example.com"[..]), 4), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"example.org"[..]), 5), InsertResult::Ok, "{}", name);
+
+    let value = |host: &[u8]| root.domain_lookup(host).map(|kv| kv.1);
+
+    // exact, then single label wildcard, then deep wildcard
+    assert_eq!(value(&b"www.example.com"[..]), Some(3), "{}", name);
+    assert_eq!(value(&b"test.example.com"[..]), Some(2), "{}", name);
+    assert_eq!(value(&b"a.test.example.com"[..]), Some(1), "{}", name);
+    // a deep wildcard covers the domain itself
+    assert_eq!(value(&b"example.com"[..]), Some(1), "{}", name);
+    assert_eq!(value(&b"Example.COM"[..]), Some(1), "{}", name);
+    // a single label wildcard wins over a deeper deep wildcard
+    assert_eq!(value(&b"deep.example.com"[..]), Some(2), "{}", name);
+    // the deepest deep wildcard wins
+    assert_eq!(value(&b"a.deep.example.com"[..]), Some(4), "{}", name);
+    assert_eq!(value(&b"a.b.deep.example.com"[..]), Some(4), "{}", name);
+    // only on label boundaries
+    assert_eq!(value(&b"xample.com"[..]), None, "{}", name);
+    assert_eq!(value(&b"anexample.com"[..]), None, "{}", name);
+    assert_eq!(value(&b"example.org"[..]), Some(5), "{}", name);
+    assert_eq!(value(&b"www.example.org"[..]), None, "{}", name);
+
+    assert_eq!(values(root, &b"www.example.com"[..]), vec![3, 2, 1], "{}", name);
+    assert_eq!(values(root, &b"deep.example.com"[..]), vec![2, 4, 1], "{}", name);
+    assert_eq!(values(root, &b"a.b.deep.example.com"[..]), vec![4, 1], "{}", name);
+    assert_eq!(values(root, &b"example.com"[..]), vec![1], "{}", name);
+  }
+
+  #[test]
+  fn deep_wildcards() {
+    let deep = ["sozu", "exp3", "exp9", "exp9 siphash", "exp11", "exp12", "exp13", "exp14", "exp15"];
+    for &(name, root) in ROOTS.iter().filter(|&&(name, _)| deep.contains(&name)) {
+      check_deep_wildcards(name, &mut *root());
+    }
+  }
+
+  // an empty first label is still one label for the single wildcard
+  fn check_empty_label<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 1), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"**.example.org"[..]), 2), InsertResult::Ok, "{}", name);
+
+    assert_eq!(values(root, &b".example.com"[..]), vec![1], "{}", name);
+    assert_eq!(values(root, &b".example.org"[..]), vec![2], "{}", name);
+    assert_eq!(values(root, &b"..example.com"[..]), Vec::<u8>::new(), "{}", name);
+  }
+
+  #[test]
+  fn empty_label() {
+    let structures = ["exp11", "exp12", "exp13", "exp14", "exp15"];
+    for &(name, root) in ROOTS.iter().filter(|&&(name, _)| structures.contains(&name)) {
+      check_empty_label(name, &mut *root());
+    }
+  }
+
+  fn check_upsert<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    assert_eq!(root.domain_upsert(Vec::from(&b"www.example.com"[..]), 1), Ok(None), "{}", name);
+    assert_eq!(root.domain_upsert(Vec::from(&b"*.example.com"[..]), 2), Ok(None), "{}", name);
+    assert_eq!(root.domain_upsert(Vec::from(&b"WWW.example.com"[..]), 3), Ok(Some(1)), "{}", name);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(3), "{}", name);
+    assert_eq!(root.domain_upsert(Vec::from(&b"www..example.com"[..]), 4), Err(InsertError::InvalidPattern), "{}", name);
+
+    // get_mut only finds the exact key
+    *root.get_mut(&b"*.example.com"[..]).expect(name) = 4;
+    assert_eq!(root.get_mut(&b"test.example.com"[..]), None, "{}", name);
+    assert_eq!(root.get_mut(&b"example.com"[..]), None, "{}", name);
+    assert_eq!(root.domain_lookup(&b"*.example.com"[..]).map(|kv| kv.1), Some(4), "{}", name);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(3), "{}", name);
+
+    *root.domain_lookup_mut(&b"WWW.Example.com"[..]).expect(name) += 2;
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(5), "{}", name);
+    assert_eq!(root.domain_lookup_mut(&b"example.org"[..]), None, "{}", name);
+  }
+
+  #[test]
+  fn upsert() {
+    for &(name, root) in ROOTS.iter() {
+      check_upsert(name, &mut *root());
+    }
+  }
+
+  const MALFORMED: [(&[u8], InsertError); 8] = [
+    (b"", InsertError::EmptyKey),
+    (b"example.com.", InsertError::InvalidPattern),
+    (b".example.com", InsertError::InvalidPattern),
+    (b"www..example.com", InsertError::InvalidPattern),
+    (b"www.*.com", InsertError::InvalidPattern),
+    (b"w*w.example.com", InsertError::InvalidPattern),
+    (b"www example.com", InsertError::InvalidCharacter),
+    ("www.ex\u{e4}mple.com".as_bytes(), InsertError::InvalidCharacter),
+  ];
+
+  fn check_malformed<T: DomainLookup<u8> + ?Sized>(name: &str, root: &mut T) {
+    for &(key, ref error) in MALFORMED.iter() {
+      assert_eq!(root.domain_insert(key.to_vec(), 1), InsertResult::Failed(error.clone()),
+        "{}: inserting {:?}", name, String::from_utf8_lossy(key));
+    }
+
+    // the structure still accepts valid keys
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 2), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 3), InsertResult::Ok, "{}", name);
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(2), "{}", name);
+  }
+
+  #[test]
+  fn malformed_keys() {
+    // exp 8 also takes regex labels, it is checked in its own module
+    for &(name, root) in ROOTS.iter().filter(|&&(name, _)| name != "exp8") {
+      check_malformed(name, &mut *root());
+    }
+  }
 }
@@ -2,7 +2,7 @@
 
 use std::fmt::Debug;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup, Iter, sort_by_specificity, is_under, validate_key};
 
 #[derive(Debug,PartialEq)]
 pub struct List<V> {
@@ -28,14 +28,60 @@ impl<V:Debug> List<V> {
     InsertResult::Ok
   }
 
+  /// every entry, in insertion order
+  pub fn iter(&self) -> Iter<V> {
+    Iter::new(self.acl.iter().map(|&(_, ref kv)| kv).collect())
+  }
+
+  /// entries for the domain and its subdomains
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    Iter::new(self.acl.iter().map(|&(_, ref kv)| kv).filter(|kv| is_under(&kv.0, domain)).collect())
+  }
+
+  pub fn len(&self) -> usize {
+    self.acl.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.acl.is_empty()
+  }
+
+  /// removes the entry stored under this ACL key, and returns it
+  pub fn remove(&mut self, key: &Key) -> Option<KeyValue<Key,V>> {
+    let pos = self.acl.iter().position(|v| &v.0 == key)?;
+    Some(self.acl.remove(pos).1)
+  }
+
   pub fn print(&self) {
     println!("{:?}", self);
   }
 }
 
+/// tests a reversed ACL key against the host, matched from its end
+fn host_matches(local_key: &[u8], key: &[u8]) -> bool {
+  let pos = key.iter().rev().zip(local_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
+  match pos {
+    None => key.len() == local_key.len(),
+    Some(i) => {
+      // check for wildcard
+      if i+1 == local_key.len() && local_key[i] == '*' as u8 {
+        let c = '.' as u8;
+        !(&key[..key.len() - i]).contains(&c)
+      } else {
+        false
+      }
+    }
+  }
+}
+
 impl<V: Debug> DomainLookup<V> for List<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     let mut partial_key = key.clone();
     partial_key.reverse();
     self.acl.push((partial_key, (key, value)));
@@ -43,16 +89,10 @@ impl<V: Debug> DomainLookup<V> for List<V> {
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
-    let mut partial_key = key.clone();
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let mut partial_key = key.to_ascii_lowercase();
     partial_key.reverse();
-    match self.acl.iter().position(|v| v.0 == partial_key) {
-      Some(pos) => {
-        self.acl.remove(pos);
-        RemoveResult::Ok
-      },
-      None => RemoveResult::NotFound
-    }
+    self.remove(&partial_key).into()
     //let mut partial_key = key.clone();
     //partial_key.reverse();
     //self.acl.remove(&partial_key)
@@ -61,39 +101,37 @@ impl<V: Debug> DomainLookup<V> for List<V> {
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    let mut partial_key = key.to_vec();
-    partial_key.reverse();
-
     for (local_key, key_value) in self.acl.iter() {
-      let pos = partial_key.iter().zip(local_key.iter()).position(|(&a,&b)| a != b);
-      match pos {
-        None => {
-          let local_len = local_key.len();
-          if partial_key.len() == local_len {
-            return Some(key_value);
-          }
-        },
-        Some(i) => {
-          // check for wildcard
-          if i+1 == local_key.len() && local_key[i] == '*' as u8 {
-            let c = '.' as u8;
-            if !(&partial_key[i..]).contains(&c) {
-              return Some(key_value);
-            }
-          }
-        }
+      if host_matches(local_key, key) {
+        return Some(key_value);
       }
     }
 
     None
   }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    self.acl.iter_mut()
+      .map(|&mut (_, ref mut kv)| kv)
+      .find(|kv| kv.0.eq_ignore_ascii_case(key))
+      .map(|kv| &mut kv.1)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut matches: Vec<&KeyValue<Key,V>> = self.acl.iter()
+      .filter(|(local_key, _)| host_matches(local_key, key))
+      .map(|(_, key_value)| key_value)
+      .collect();
+    sort_by_specificity(&mut matches);
+    matches
+  }
+
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
 
-  /*
   #[test]
   fn insert() {
     let mut root: List<u8> = List::root();
@@ -133,7 +171,8 @@ mod tests {
 
     println!("before remove");
     root.print();
-    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), Some((Vec::from(&b"abce"[..]), 2)));
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), None);
     println!("after remove");
     root.print();
 
@@ -141,7 +180,7 @@ mod tests {
     root2.print();
     assert_eq!(root, root2);
 
-    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), Some((Vec::from(&b"abgh"[..]), 3)));
     println!("after remove");
     root.print();
     println!("expected");
@@ -177,7 +216,7 @@ mod tests {
 
     println!("root2:");
     root2.print();
-    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok);
+    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), Some((Vec::from(&b"abc"[..]), 3)));
 
     println!("root2 after,remove:");
     root2.print();
@@ -192,7 +231,6 @@ mod tests {
     expected.print();
     assert_eq!(root2, expected);
   }
-  */
 
   #[test]
   fn domains() {
@@ -218,7 +256,7 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
-    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
     println!("after remove");
     root.print();
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
@@ -1,7 +1,7 @@
 use std::{iter,str};
 use std::fmt::Debug;
 
-use super::{Key, KeyValue, InsertResult, RemoveResult, DomainLookup};
+use super::{Key, KeyValue, InsertResult, InsertError, RemoveResult, DomainLookup, Iter, sort_by_specificity, most_specific, specificity, is_under, covers_subtree, validate_key};
 
 #[derive(Debug,PartialEq)]
 pub struct TrieNode<V> {
@@ -43,13 +43,14 @@ impl<V:Debug> TrieNode<V> {
 
   pub fn insert(&mut self, key: Key, value: V) -> InsertResult {
     let res = self.insert_recursive(&key, &key, value);
-    assert_ne!(res, InsertResult::Failed);
     //println!("adding {}", str::from_utf8(&key).unwrap());
     res
   }
 
   pub fn insert_recursive(&mut self, partial_key: &[u8], key: &Key, value: V) -> InsertResult {
-    assert_ne!(partial_key, &b""[..]);
+    if partial_key.is_empty() {
+      return InsertResult::Failed(InsertError::EmptyKey);
+    }
 
     // checking directly the children
     for child in self.children.iter_mut() {
@@ -98,11 +99,12 @@ impl<V:Debug> TrieNode<V> {
     InsertResult::Ok
   }
 
-  pub fn remove(&mut self, key: &Key) -> RemoveResult {
+  /// removes the reversed key, and returns the entry
+  pub fn remove(&mut self, key: &Key) -> Option<KeyValue<Key,V>> {
     self.remove_recursive(key)
   }
 
-  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> RemoveResult {
+  pub fn remove_recursive(&mut self, partial_key: &[u8]) -> Option<KeyValue<Key,V>> {
     assert_ne!(partial_key, &b""[..]);
     let mut found_child:Option<usize> = None;
 
@@ -127,13 +129,16 @@ impl<V:Debug> TrieNode<V> {
     }
 
     if let Some(index) = found_child {
-      if self.children[index].children.len() > 0 && self.children[index].key_value.is_some() {
-        self.children[index].key_value = None;
-        return RemoveResult::Ok;
+      // an inner node without a value: the key was not inserted, its
+      // children stay in place
+      if self.children[index].key_value.is_none() {
+        None
+      } else if !self.children[index].children.is_empty() {
+        self.children[index].key_value.take()
       } else {
-        self.children.remove(index);
+        let removed = self.children.remove(index).key_value;
         if self.key_value.is_some() {
-          return RemoveResult::Ok;
+          removed
         } else {
           //merging with the child
           if self.children.len() == 1 {
@@ -146,11 +151,61 @@ impl<V:Debug> TrieNode<V> {
           // this case should only happen if it is the root node
           // otherwise, when we get to one last node and there is
           // no key_value, it is merged with the child node
-          return RemoveResult::Ok
+          removed
         }
       }
     } else {
-      RemoveResult::NotFound
+      None
+    }
+  }
+
+  /// removes the entries for the domain and its subdomains in one walk, and
+  /// returns them. A domain starting with a dot only covers its subdomains
+  pub fn remove_subtree(&mut self, domain: &[u8]) -> Vec<KeyValue<Key,V>> {
+    let mut removed = Vec::new();
+    if self.remove_subtree_recursive(domain, domain, &mut removed) {
+      ::std::mem::replace(self, TrieNode::root()).into_entries(&mut removed);
+    } else if self.key_value.is_none() && self.children.is_empty() {
+      *self = TrieNode::root();
+    }
+    removed
+  }
+
+  // `rest` is the part of the domain not matched yet, from its end. Returns
+  // true if every entry of this node is under the domain, so that the parent
+  // detaches the node whole instead of visiting it
+  fn remove_subtree_recursive(&mut self, domain: &[u8], rest: &[u8], removed: &mut Vec<KeyValue<Key,V>>) -> bool {
+    if rest.iter().rev().zip(self.partial_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+      return false;
+    }
+
+    if rest.len() < self.partial_key.len() {
+      return covers_subtree(domain, self.partial_key[rest.len()]);
+    }
+
+    if rest.len() == self.partial_key.len() && self.key_value.as_ref().map(|kv| is_under(&kv.0, domain)).unwrap_or(false) {
+      removed.extend(self.key_value.take());
+    }
+
+    let rest = &rest[..rest.len() - self.partial_key.len()];
+    let mut index = 0;
+    while index < self.children.len() {
+      if self.children[index].remove_subtree_recursive(domain, rest, removed) {
+        self.children.remove(index).into_entries(removed);
+      } else if self.children[index].key_value.is_none() && self.children[index].children.is_empty() {
+        self.children.remove(index);
+      } else {
+        index += 1;
+      }
+    }
+
+    false
+  }
+
+  fn into_entries(self, entries: &mut Vec<KeyValue<Key,V>>) {
+    entries.extend(self.key_value);
+    for child in self.children {
+      child.into_entries(entries);
     }
   }
 
@@ -175,41 +230,192 @@ impl<V:Debug> TrieNode<V> {
   }
 
   // specific version that will handle wildcard domains
-  pub fn domain_lookup_recursive(&self, partial_key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    assert_ne!(partial_key, &b""[..]);
-    let pos = partial_key.iter().zip(self.partial_key.iter()).position(|(&a,&b)| a != b);
+  // the host is matched from its end, `host` is the part not matched yet
+  pub fn domain_lookup_recursive(&self, host: &[u8]) -> Option<&KeyValue<Key,V>> {
+    // an empty host cannot match any key
+    if host.is_empty() {
+      return None;
+    }
+    let pos = host.iter().rev().zip(self.partial_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
     //println!("lookup at level: {}, testing {}", str::from_utf8(&self.partial_key).unwrap(),
-    //  str::from_utf8(partial_key).unwrap());
+    //  str::from_utf8(host).unwrap());
 
     match pos {
       Some(i) => {
         // check for wildcard
         if i+1 == self.partial_key.len() && self.partial_key[i] == '*' as u8 {
           let c = '.' as u8;
-          if (&partial_key[i..]).contains(&c) {
-            None
-          } else {
-            self.key_value.as_ref()
+          if !(&host[..host.len() - i]).contains(&c) {
+            return self.key_value.as_ref();
           }
+        }
+
+        self.find_rest(i, b"**")
+      },
+      None    => {
+        if host.len() > self.partial_key.len() {
+          let rest = &host[..host.len() - self.partial_key.len()];
+          let next = rest[rest.len() - 1];
+
+          // the child for the next byte is tried first: when it finds an exact
+          // key or a single label wildcard, a wildcard child of this node
+          // cannot be more specific
+          let exact = self.children.iter()
+            .find(|child| child.partial_key.first().is_some_and(|c| c.eq_ignore_ascii_case(&next)))
+            .and_then(|child| child.domain_lookup_recursive(rest));
+
+          match exact {
+            Some(kv) if specificity(&kv.0) < 2 => Some(kv),
+            exact => self.children.iter()
+              .filter(|child| child.partial_key.first() == Some(&b'*'))
+              .fold(exact, |res, child| most_specific(res, child.domain_lookup_recursive(rest))),
+          }
+        } else if host.len() == self.partial_key.len() {
+          self.key_value.as_ref().or_else(|| self.find_rest(self.partial_key.len(), b".**"))
         } else {
-          None
+          self.find_rest(host.len(), b".**")
+        }
+
+      }
+    }
+  }
+
+  // collects every key matching the host, in the order of the children
+  pub fn domain_lookup_all_recursive<'a>(&'a self, host: &[u8], matches: &mut Vec<&'a KeyValue<Key,V>>) {
+    // an empty host cannot match any key
+    if host.is_empty() {
+      return;
+    }
+    let pos = host.iter().rev().zip(self.partial_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
+
+    match pos {
+      Some(i) => {
+        // check for wildcard
+        if i+1 == self.partial_key.len() && self.partial_key[i] == '*' as u8 {
+          let c = '.' as u8;
+          if !(&host[..host.len() - i]).contains(&c) {
+            matches.extend(self.key_value.as_ref());
+          }
         }
+
+        matches.extend(self.find_rest(i, b"**"));
       },
       None    => {
-        if partial_key.len() > self.partial_key.len() {
+        if host.len() > self.partial_key.len() {
           for child in self.children.iter() {
-            let res = child.domain_lookup_recursive(&partial_key[self.partial_key.len()..]);
-            if res.is_some() {
-              return res
-            }
+            child.domain_lookup_all_recursive(&host[..host.len() - self.partial_key.len()], matches);
           }
-          None
-        } else if partial_key.len() == self.partial_key.len() {
-          self.key_value.as_ref()
+        } else if host.len() == self.partial_key.len() {
+          matches.extend(self.key_value.as_ref());
+          matches.extend(self.find_rest(self.partial_key.len(), b".**"));
         } else {
-          None
+          matches.extend(self.find_rest(host.len(), b".**"));
         }
+      }
+    }
+  }
+
+  // deep wildcards: once the host is matched up to a label, the key of
+  // `**.domain` continues with `rest`, from `offset` in this node's partial key
+  fn find_rest(&self, offset: usize, rest: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let partial_key = &self.partial_key[offset..];
+    if !rest.starts_with(partial_key) {
+      return None;
+    }
+
+    let rest = &rest[partial_key.len()..];
+    if rest.is_empty() {
+      return self.key_value.as_ref();
+    }
+
+    self.children.iter().filter_map(|child| child.find_rest(0, rest)).next()
+  }
 
+  // deepest key that is the host or one of its parent domains
+  pub fn domain_lookup_closest_recursive(&self, host: &[u8]) -> Option<&KeyValue<Key,V>> {
+    let pos = host.iter().rev().zip(self.partial_key.iter()).position(|(&a,&b)| !a.eq_ignore_ascii_case(&b));
+    if pos.is_some() || host.len() < self.partial_key.len() {
+      return None;
+    }
+
+    if host.len() == self.partial_key.len() {
+      return self.key_value.as_ref();
+    }
+
+    let rest = &host[..host.len() - self.partial_key.len()];
+    for child in self.children.iter() {
+      let res = child.domain_lookup_closest_recursive(rest);
+      if res.is_some() {
+        return res
+      }
+    }
+
+    // this node is a parent domain only if it ends on a label
+    if rest[rest.len() - 1] == b'.' {
+      self.key_value.as_ref()
+    } else {
+      None
+    }
+  }
+
+  // exact version of `domain_lookup_recursive`, matching the key from its end
+  fn get_mut_recursive(&mut self, key: &[u8]) -> Option<&mut V> {
+    if key.len() < self.partial_key.len() ||
+      key.iter().rev().zip(self.partial_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+      return None;
+    }
+
+    if key.len() == self.partial_key.len() {
+      return self.key_value.as_mut().map(|kv| &mut kv.1);
+    }
+
+    let rest = &key[..key.len() - self.partial_key.len()];
+    self.children.iter_mut().filter_map(|child| child.get_mut_recursive(rest)).next()
+  }
+
+  /// every entry stored in the trie, as `(&Key, &V)`
+  pub fn iter(&self) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect(&mut entries);
+    Iter::new(entries)
+  }
+
+  /// entries for the domain and its subdomains. They are stored under the
+  /// reversed domain, so only that subtree is visited
+  pub fn iter_under(&self, domain: &[u8]) -> Iter<V> {
+    let mut entries = Vec::new();
+    self.collect_under(domain, &mut entries);
+    entries.retain(|kv| is_under(&kv.0, domain));
+    Iter::new(entries)
+  }
+
+  pub fn len(&self) -> usize {
+    self.children.iter().fold(self.key_value.iter().count(), |acc, c| acc + c.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.key_value.is_none() && self.children.iter().all(|c| c.is_empty())
+  }
+
+  fn collect<'a>(&'a self, entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    entries.extend(self.key_value.as_ref());
+    for child in self.children.iter() {
+      child.collect(entries);
+    }
+  }
+
+  // the domain is matched from its end, like hosts in lookups
+  fn collect_under<'a>(&'a self, domain: &[u8], entries: &mut Vec<&'a KeyValue<Key,V>>) {
+    if domain.iter().rev().zip(self.partial_key.iter()).any(|(a,b)| !a.eq_ignore_ascii_case(b)) {
+      return;
+    }
+
+    if domain.len() <= self.partial_key.len() {
+      self.collect(entries);
+    } else {
+      let rest = &domain[..domain.len() - self.partial_key.len()];
+      for child in self.children.iter() {
+        child.collect_under(rest, entries);
       }
     }
   }
@@ -236,24 +442,42 @@ impl<V:Debug> TrieNode<V> {
 
 impl<V: Debug> DomainLookup<V> for TrieNode<V> {
   // specific version that will handle wildcard domains
-  fn domain_insert(&mut self, key: Key, value: V) -> InsertResult {
+  fn domain_insert(&mut self, mut key: Key, value: V) -> InsertResult {
+    key.make_ascii_lowercase();
+    if let Err(e) = validate_key(&key) {
+      return InsertResult::Failed(e);
+    }
+
     let mut partial_key = key.clone();
     partial_key.reverse();
     self.insert_recursive(&partial_key, &key, value)
   }
 
   // specific version that will handle wildcard domains
-  fn domain_remove(&mut self, key: &Key) -> RemoveResult {
-    let mut partial_key = key.clone();
+  fn domain_remove(&mut self, key: &Key) -> RemoveResult<V> {
+    let mut partial_key = key.to_ascii_lowercase();
     partial_key.reverse();
-    self.remove_recursive(&partial_key)
+    self.remove_recursive(&partial_key).into()
   }
 
   // specific version that will handle wildcard domains
   fn domain_lookup(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
-    let mut partial_key = key.to_vec();
-    partial_key.reverse();
-    self.domain_lookup_recursive(&partial_key)
+    self.domain_lookup_recursive(key)
+  }
+
+  fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+    self.get_mut_recursive(key)
+  }
+
+  fn domain_lookup_all(&self, key: &[u8]) -> Vec<&KeyValue<Key,V>> {
+    let mut matches = Vec::new();
+    self.domain_lookup_all_recursive(key, &mut matches);
+    sort_by_specificity(&mut matches);
+    matches
+  }
+
+  fn domain_lookup_closest(&self, key: &[u8]) -> Option<&KeyValue<Key,V>> {
+    self.domain_lookup_closest_recursive(key)
   }
 }
 
@@ -292,14 +516,15 @@ mod tests {
 
     println!("before remove");
     root.print();
-    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), Some((Vec::from(&b"abce"[..]), 2)));
+    assert_eq!(root.remove(&Vec::from(&b"abce"[..])), None);
     println!("after remove");
     root.print();
     println!("expected");
     root2.print();
     assert_eq!(root, root2);
 
-    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), RemoveResult::Ok);
+    assert_eq!(root.remove(&Vec::from(&b"abgh"[..])), Some((Vec::from(&b"abgh"[..]), 3)));
     println!("after remove");
     root.print();
     println!("expected");
@@ -309,6 +534,19 @@ mod tests {
     assert_eq!(root, root3);
   }
 
+  #[test]
+  fn remove_inner_node() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"*.x.x.ab.ab"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.a.b"[..]), 2), InsertResult::Ok);
+
+    // "b" is only the node shared by both keys
+    assert_eq!(root.domain_remove(&Vec::from(&b"b"[..])), RemoveResult::NotFound);
+    assert_eq!(root.domain_lookup(&b"test.x.x.ab.ab"[..]).map(|kv| kv.1), Some(1));
+    assert_eq!(root.domain_lookup(&b"test.a.b"[..]).map(|kv| kv.1), Some(2));
+  }
+
   #[test]
   fn add_child_to_leaf() {
     let mut root: TrieNode<u8> = TrieNode::root();
@@ -326,7 +564,7 @@ mod tests {
     assert_eq!(root2.insert(Vec::from(&b"abce"[..]), 2), InsertResult::Ok);
 
     root2.print();
-    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), RemoveResult::Ok);
+    assert_eq!(root2.remove(&Vec::from(&b"abc"[..])), Some((Vec::from(&b"abc"[..]), 3)));
 
     let mut expected: TrieNode<u8> = TrieNode::root();
 
@@ -363,7 +601,7 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
 
-    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok);
+    assert_eq!(root.domain_remove(&Vec::from(&b"alldomains.org"[..])), RemoveResult::Ok(4));
     println!("after remove");
     root.print();
     assert_eq!(root.domain_lookup(&b"alldomains.org"[..]), None);
@@ -371,4 +609,17 @@ mod tests {
     assert_eq!(root.domain_lookup(&b"hello.alldomains.org"[..]), Some(&((&b"*.alldomains.org"[..]).to_vec(), 3)));
     assert_eq!(root.domain_lookup(&b"blah.test.alldomains.org"[..]), None);
   }
+
+  #[test]
+  fn lookup_mut() {
+    let mut root: TrieNode<u8> = TrieNode::root();
+
+    assert_eq!(root.domain_insert(Vec::from(&b"www.example.com"[..]), 1), InsertResult::Ok);
+    assert_eq!(root.domain_insert(Vec::from(&b"*.example.com"[..]), 2), InsertResult::Ok);
+
+    // domain_lookup_mut matches wildcards like domain_lookup
+    *root.domain_lookup_mut(&b"Test.example.com"[..]).unwrap() += 1;
+    assert_eq!(root.domain_lookup(&b"test.example.com"[..]).map(|kv| kv.1), Some(3));
+    assert_eq!(root.domain_lookup(&b"www.example.com"[..]).map(|kv| kv.1), Some(1));
+  }
 }